@@ -0,0 +1,200 @@
+//! Conditions that abort a run early.
+use crate::histogram::LatencyHistogram;
+use crate::run::{RequestResult, Seconds, StopReason};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Conditions checked by `Runner` as the results arrive.
+///
+/// `None` disables the corresponding check.
+#[derive(Debug, Clone)]
+pub struct AbortConditions {
+    /// Maximum error rate (in percent) of the last `window` results.
+    pub max_error_rate: Option<f64>,
+
+    /// Maximum number of consecutive errors (and assertion failures).
+    pub max_consecutive_failures: Option<usize>,
+
+    /// Maximum latency at `latency_percentile` of the last `window` results.
+    ///
+    /// This is checked once every `window` results.
+    pub max_latency: Option<Seconds>,
+    pub latency_percentile: f64,
+
+    /// Number of the latest results used to compute the error rate and the latency percentile.
+    ///
+    /// These are not checked until this number of results have arrived.
+    pub window: usize,
+
+    /// Maximum duration of the whole run.
+    ///
+    /// Unlike `RunnerBuilder::duration`, the in-flight requests are not waited for.
+    pub max_wall_time: Option<Duration>,
+}
+impl AbortConditions {
+    pub fn is_empty(&self) -> bool {
+        self.max_error_rate.is_none()
+            && self.max_consecutive_failures.is_none()
+            && self.max_latency.is_none()
+            && self.max_wall_time.is_none()
+    }
+}
+impl Default for AbortConditions {
+    fn default() -> Self {
+        AbortConditions {
+            max_error_rate: None,
+            max_consecutive_failures: None,
+            max_latency: None,
+            latency_percentile: 99.0,
+            window: 100,
+            max_wall_time: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct AbortChecker {
+    conditions: AbortConditions,
+    window: VecDeque<bool>,
+    failures_in_window: usize,
+    latencies: LatencyHistogram,
+    consecutive_failures: usize,
+}
+impl AbortChecker {
+    pub fn new(conditions: AbortConditions) -> Self {
+        AbortChecker {
+            window: VecDeque::with_capacity(conditions.window),
+            conditions,
+            failures_in_window: 0,
+            latencies: LatencyHistogram::new(),
+            consecutive_failures: 0,
+        }
+    }
+
+    /// Records a result and returns the reason if any of the conditions is met.
+    pub fn record(&mut self, result: &RequestResult) -> Option<StopReason> {
+        let failed = !result.is_ok();
+        if failed {
+            self.consecutive_failures += 1;
+            self.failures_in_window += 1;
+        } else {
+            self.consecutive_failures = 0;
+        }
+        self.window.push_back(failed);
+        self.latencies.record(result.elapsed());
+        if self.window.len() > self.conditions.window {
+            if let Some(true) = self.window.pop_front() {
+                self.failures_in_window -= 1;
+            }
+        }
+
+        if let Some(max) = self.conditions.max_consecutive_failures {
+            if self.consecutive_failures >= max {
+                return Some(StopReason::ConsecutiveFailures {
+                    count: self.consecutive_failures,
+                });
+            }
+        }
+        if self.window.len() < self.conditions.window {
+            return None;
+        }
+        if let Some(max) = self.conditions.max_error_rate {
+            let error_rate = self.failures_in_window as f64 / self.window.len() as f64 * 100.0;
+            if error_rate > max {
+                return Some(StopReason::ErrorRate { error_rate, max });
+            }
+        }
+        if self.latencies.len() < self.conditions.window as u64 {
+            return None;
+        }
+        let latencies = std::mem::take(&mut self.latencies);
+        if let Some(max) = self.conditions.max_latency {
+            let percentile = self.conditions.latency_percentile;
+            let latency = latencies.percentile(percentile);
+            if latency > max {
+                return Some(StopReason::Latency {
+                    percentile,
+                    latency,
+                    max,
+                });
+            }
+        }
+        None
+    }
+
+    /// Returns the reason if the run has exceeded the maximum wall time.
+    pub fn check_wall_time(&self, elapsed: Duration) -> Option<StopReason> {
+        let max = self.conditions.max_wall_time?;
+        if elapsed >= max {
+            Some(StopReason::WallTime { max: max.into() })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::run::Labels;
+    use crate::{Error, ErrorKind};
+
+    fn result(ok: bool) -> RequestResult {
+        if ok {
+            RequestResult::ok_for_test(0, 1.0, 0.1)
+        } else {
+            RequestResult::Error {
+                seq_no: 0,
+                end_time: Seconds(1.0),
+                elapsed: Seconds(0.1),
+                scheduled_start_time: None,
+                labels: Labels::default(),
+                error: Error::from(ErrorKind::Timeout),
+            }
+        }
+    }
+
+    #[test]
+    fn abort_checker_works() {
+        let mut checker = AbortChecker::new(AbortConditions {
+            max_error_rate: Some(50.0),
+            max_consecutive_failures: Some(3),
+            window: 4,
+            ..AbortConditions::default()
+        });
+        assert!(checker.record(&result(false)).is_none());
+        assert!(checker.record(&result(true)).is_none());
+        assert!(checker.record(&result(false)).is_none());
+        assert!(checker.record(&result(true)).is_none());
+        assert!(checker.record(&result(false)).is_none()); // 50%
+        assert!(matches!(
+            checker.record(&result(false)),
+            Some(StopReason::ErrorRate { .. })
+        ));
+        assert!(matches!(
+            checker.record(&result(false)),
+            Some(StopReason::ConsecutiveFailures { count: 3 })
+        ));
+    }
+
+    #[test]
+    fn latency_is_checked_once_per_window() {
+        let mut checker = AbortChecker::new(AbortConditions {
+            max_latency: Some(Seconds(0.5)),
+            latency_percentile: 50.0,
+            window: 3,
+            ..AbortConditions::default()
+        });
+        let slow = RequestResult::ok_for_test(0, 1.0, 1.0);
+        let fast = RequestResult::ok_for_test(0, 1.0, 0.1);
+        assert!(checker.record(&slow).is_none());
+        assert!(checker.record(&fast).is_none());
+        assert!(checker.record(&fast).is_none()); // p50 = 0.1s
+        assert!(checker.record(&slow).is_none());
+        assert!(checker.record(&slow).is_none());
+        assert!(matches!(
+            checker.record(&fast),
+            Some(StopReason::Latency { percentile, .. }) if percentile == 50.0
+        ));
+    }
+}
@@ -0,0 +1,356 @@
+//! Comparison of the results of two runs (a baseline and a candidate).
+use crate::run::{RequestResult, Seconds};
+use crate::summary::{Summary, SummaryBuilder, DEFAULT_PERCENTILES};
+use crate::{ErrorKind, Result};
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+
+#[derive(Debug, Clone)]
+pub struct ComparisonBuilder {
+    percentiles: Vec<f64>,
+    thresholds: Thresholds,
+}
+impl ComparisonBuilder {
+    pub fn new() -> Self {
+        ComparisonBuilder::default()
+    }
+
+    /// Sets the latency percentiles to compare.
+    pub fn percentiles(&mut self, percentiles: Vec<f64>) -> &mut Self {
+        self.percentiles = percentiles;
+        self
+    }
+
+    /// Sets the thresholds used to decide whether the candidate has regressed.
+    pub fn thresholds(&mut self, thresholds: Thresholds) -> &mut Self {
+        self.thresholds = thresholds;
+        self
+    }
+
+    pub fn finish(
+        &self,
+        baseline: Vec<RequestResult>,
+        candidate: Vec<RequestResult>,
+    ) -> Result<Comparison> {
+        track_assert!(!baseline.is_empty(), ErrorKind::Other, "Empty baseline");
+        track_assert!(!candidate.is_empty(), ErrorKind::Other, "Empty candidate");
+
+        let latencies = |results: &[RequestResult]| {
+            results
+                .iter()
+                .filter(|r| r.is_ok())
+                .map(|r| r.elapsed())
+                .collect::<Vec<_>>()
+        };
+        let mann_whitney_u = MannWhitneyU::new(&latencies(&baseline), &latencies(&candidate));
+
+        let mut builder = SummaryBuilder::new();
+        builder.percentiles(self.percentiles.clone());
+        let baseline = builder.finish(baseline);
+        let candidate = builder.finish(candidate);
+
+        let rps = Delta::new(baseline.rps, candidate.rps);
+        let error_rate = Delta::new(baseline.error_rate(), candidate.error_rate());
+        let latency = LatencyDeltas(
+            baseline
+                .latency
+                .percentiles
+                .0
+                .iter()
+                .zip(candidate.latency.percentiles.0.iter())
+                .map(|(&(p, b), &(_, c))| (p, Delta::new(b.0, c.0)))
+                .collect(),
+        );
+
+        let mut regressions = Vec::new();
+        if let Some(max) = self.thresholds.max_rps_decrease {
+            if let Some(decrease) = rps.relative_change().map(|c| -c).filter(|&d| d > max) {
+                regressions.push(format!(
+                    "Throughput decreased by {:.2}% (threshold: {}%)",
+                    decrease, max
+                ));
+            }
+        }
+        if let Some(max) = self.thresholds.max_error_rate_increase {
+            let increase = error_rate.change * 100.0;
+            if increase > max {
+                regressions.push(format!(
+                    "Error rate increased by {:.2} percentage points (threshold: {})",
+                    increase, max
+                ));
+            }
+        }
+        if let Some(max) = self.thresholds.max_latency_increase {
+            // Latency changes are only regarded as regressions if they are statistically significant.
+            if mann_whitney_u.p_value < self.thresholds.alpha {
+                for (p, delta) in &latency.0 {
+                    if let Some(increase) = delta.relative_change().filter(|&c| c > max) {
+                        regressions.push(format!(
+                            "p{} latency increased by {:.2}% (threshold: {}%, p-value: {:.4})",
+                            p, increase, max, mann_whitney_u.p_value
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(Comparison {
+            baseline,
+            candidate,
+            rps,
+            error_rate,
+            latency,
+            mann_whitney_u,
+            regressions,
+        })
+    }
+}
+impl Default for ComparisonBuilder {
+    fn default() -> Self {
+        ComparisonBuilder {
+            percentiles: DEFAULT_PERCENTILES.to_vec(),
+            thresholds: Thresholds::default(),
+        }
+    }
+}
+
+/// Regression thresholds.
+///
+/// `None` disables the corresponding check.
+#[derive(Debug, Clone)]
+pub struct Thresholds {
+    /// Maximum allowed decrease of the throughput in percent.
+    pub max_rps_decrease: Option<f64>,
+
+    /// Maximum allowed increase of the error rate in percentage points.
+    pub max_error_rate_increase: Option<f64>,
+
+    /// Maximum allowed increase of each latency percentile in percent.
+    pub max_latency_increase: Option<f64>,
+
+    /// Significance level of the Mann-Whitney U test.
+    pub alpha: f64,
+}
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            max_rps_decrease: None,
+            max_error_rate_increase: None,
+            max_latency_increase: None,
+            alpha: 0.05,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Comparison {
+    pub baseline: Summary,
+    pub candidate: Summary,
+    pub rps: Delta,
+    pub error_rate: Delta,
+    pub latency: LatencyDeltas,
+    pub mann_whitney_u: MannWhitneyU,
+
+    /// Descriptions of the exceeded thresholds.
+    pub regressions: Vec<String>,
+}
+impl Comparison {
+    pub fn has_regressions(&self) -> bool {
+        !self.regressions.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Delta {
+    pub baseline: f64,
+    pub candidate: f64,
+
+    /// `candidate - baseline`
+    pub change: f64,
+}
+impl Delta {
+    fn new(baseline: f64, candidate: f64) -> Self {
+        Delta {
+            baseline,
+            candidate,
+            change: candidate - baseline,
+        }
+    }
+
+    /// Returns the change relative to the baseline in percent.
+    ///
+    /// `None` if the baseline is zero and the candidate is not.
+    pub fn relative_change(&self) -> Option<f64> {
+        if self.change == 0.0 {
+            Some(0.0)
+        } else if self.baseline == 0.0 {
+            None
+        } else {
+            Some(self.change / self.baseline * 100.0)
+        }
+    }
+}
+
+/// Latency deltas at the compared percentiles, serialized as a map such as `{"p50": {..}}`.
+#[derive(Debug, Clone)]
+pub struct LatencyDeltas(pub Vec<(f64, Delta)>);
+impl Serialize for LatencyDeltas {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (p, delta) in &self.0 {
+            map.serialize_entry(&format!("p{}", p), delta)?;
+        }
+        map.end()
+    }
+}
+
+/// Result of the (two-sided) Mann-Whitney U test on the latency distributions of the successful results.
+///
+/// The p-value is computed by the normal approximation with tie and continuity corrections.
+/// If either run has no successful results, the p-value is 1 and the effect size is 0.5.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct MannWhitneyU {
+    /// The U statistic of the candidate.
+    pub u: f64,
+    pub z: f64,
+    pub p_value: f64,
+
+    /// Probability that a candidate latency is greater than a baseline one (ties count as half).
+    pub effect_size: f64,
+}
+impl MannWhitneyU {
+    fn new(baseline: &[Seconds], candidate: &[Seconds]) -> Self {
+        let n1 = baseline.len() as f64;
+        let n2 = candidate.len() as f64;
+        let mut samples = baseline
+            .iter()
+            .map(|s| (s.0, false))
+            .chain(candidate.iter().map(|s| (s.0, true)))
+            .collect::<Vec<_>>();
+        samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut candidate_rank_sum = 0.0;
+        let mut tie_term = 0.0;
+        let mut i = 0;
+        while i < samples.len() {
+            let j = i + samples[i..]
+                .iter()
+                .take_while(|s| s.0 == samples[i].0)
+                .count();
+            let rank = (i + 1 + j) as f64 / 2.0;
+            let ties = (j - i) as f64;
+            tie_term += ties * ties * ties - ties;
+            candidate_rank_sum += rank * samples[i..j].iter().filter(|s| s.1).count() as f64;
+            i = j;
+        }
+
+        let u = candidate_rank_sum - n2 * (n2 + 1.0) / 2.0;
+        let n = n1 + n2;
+        let mean = n1 * n2 / 2.0;
+        let sd = (n1 * n2 / 12.0 * ((n + 1.0) - tie_term / (n * (n - 1.0)))).sqrt();
+        let (z, p_value) = if sd > 0.0 {
+            let diff = u - mean;
+            let z = diff.signum() * (diff.abs() - 0.5).max(0.0) / sd;
+            (z, erfc(z.abs() / std::f64::consts::SQRT_2).min(1.0))
+        } else {
+            (0.0, 1.0)
+        };
+        MannWhitneyU {
+            u,
+            z,
+            p_value,
+            effect_size: if n1 * n2 > 0.0 { u / (n1 * n2) } else { 0.5 },
+        }
+    }
+}
+
+/// Complementary error function (fractional error less than 1.2e-7; Numerical Recipes' `erfcc`).
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t
+        * (-z * z - 1.265_512_23
+            + t * (1.000_023_68
+                + t * (0.374_091_96
+                    + t * (0.096_784_18
+                        + t * (-0.186_288_06
+                            + t * (0.278_868_07
+                                + t * (-1.135_203_98
+                                    + t * (1.488_515_87
+                                        + t * (-0.822_152_23 + t * 0.170_872_77)))))))))
+            .exp();
+    if x >= 0.0 {
+        r
+    } else {
+        2.0 - r
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mann_whitney_u_works() {
+        let baseline = [1.1, 2.3, 3.0, 4.5, 5.2, 6.0, 7.4, 8.1]
+            .iter()
+            .cloned()
+            .map(Seconds)
+            .collect::<Vec<_>>();
+        let same = MannWhitneyU::new(&baseline, &baseline);
+        assert_eq!(same.u, 32.0);
+        assert_eq!(same.p_value, 1.0);
+
+        let slower = baseline
+            .iter()
+            .map(|s| Seconds(s.0 + 10.0))
+            .collect::<Vec<_>>();
+        let result = MannWhitneyU::new(&baseline, &slower);
+        assert_eq!(result.u, 64.0);
+        assert_eq!(result.effect_size, 1.0);
+        assert!(result.z > 3.0);
+        assert!(result.p_value < 0.01);
+    }
+
+    #[test]
+    fn relative_change_of_zero_baseline_is_none() {
+        assert_eq!(Delta::new(0.0, 0.0).relative_change(), Some(0.0));
+        assert_eq!(Delta::new(0.0, 1.0).relative_change(), None);
+        assert_eq!(Delta::new(2.0, 1.0).relative_change(), Some(-50.0));
+    }
+
+    #[test]
+    fn latencies_of_failures_are_not_tested() {
+        let baseline = (0..10)
+            .map(|i| RequestResult::ok_for_test(i, 1.0, 0.1))
+            .collect::<Vec<_>>();
+        let mut candidate = baseline.clone();
+        candidate.push(RequestResult::Error {
+            seq_no: 10,
+            end_time: Seconds(1.0),
+            elapsed: Seconds(5.0),
+            scheduled_start_time: None,
+            labels: Default::default(),
+            error: crate::Error::from(ErrorKind::Timeout),
+        });
+        let comparison = ComparisonBuilder::new()
+            .finish(baseline.clone(), candidate)
+            .unwrap();
+        assert_eq!(comparison.mann_whitney_u.p_value, 1.0);
+        assert_eq!(comparison.mann_whitney_u.effect_size, 0.5);
+
+        let failures = vec![RequestResult::Error {
+            seq_no: 0,
+            end_time: Seconds(1.0),
+            elapsed: Seconds(5.0),
+            scheduled_start_time: None,
+            labels: Default::default(),
+            error: crate::Error::from(ErrorKind::Timeout),
+        }];
+        let comparison = ComparisonBuilder::new().finish(baseline, failures).unwrap();
+        assert_eq!(comparison.mann_whitney_u.p_value, 1.0);
+    }
+}
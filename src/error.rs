@@ -6,18 +6,48 @@ use std::sync::PoisonError;
 use trackable::error::TrackableError;
 use trackable::error::{ErrorKind as TrackableErrorKind, ErrorKindExt};
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum ErrorKind {
     Timeout,
+
+    /// Name resolution of the target host failed.
+    Dns,
+    ConnectionRefused,
+
+    /// The connection was reset or closed by the peer before the response was completed.
+    ConnectionReset,
+    Tls,
+
+    /// The response could not be parsed as HTTP.
+    MalformedResponse,
+
+    /// No connection was available because the connection pool was full.
+    PoolExhausted,
+
+    /// The request (or a setting) is invalid (e.g., a header value contains CR or LF).
+    InvalidInput,
     Other,
 }
+impl ErrorKind {
+    fn from_io_error(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::TimedOut => ErrorKind::Timeout,
+            io::ErrorKind::ConnectionRefused => ErrorKind::ConnectionRefused,
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof => ErrorKind::ConnectionReset,
+            _ => ErrorKind::Other,
+        }
+    }
+}
 impl TrackableErrorKind for ErrorKind {}
 
 #[derive(Debug, Clone, Serialize, Deserialize, trackable::TrackableError)]
 pub struct Error(TrackableError<ErrorKind>);
 impl From<io::Error> for Error {
     fn from(f: io::Error) -> Self {
-        ErrorKind::Other.cause(f).into()
+        ErrorKind::from_io_error(&f).cause(f).into()
     }
 }
 impl From<std::net::AddrParseError> for Error {
@@ -25,21 +55,55 @@ impl From<std::net::AddrParseError> for Error {
         ErrorKind::Other.cause(f).into()
     }
 }
-impl From<fibers_http_client::Error> for Error {
-    fn from(f: fibers_http_client::Error) -> Self {
+impl From<url::ParseError> for Error {
+    fn from(f: url::ParseError) -> Self {
+        ErrorKind::Other.cause(f).into()
+    }
+}
+impl From<bytecodec::Error> for Error {
+    fn from(f: bytecodec::Error) -> Self {
         let original_error_kind = *f.kind();
         let kind = match original_error_kind {
-            fibers_http_client::ErrorKind::Timeout => ErrorKind::Timeout,
-            _ => ErrorKind::Other,
+            // NOTE: Invalid input to the response decoder is classified as `MalformedResponse` by `http`.
+            bytecodec::ErrorKind::InvalidInput => ErrorKind::InvalidInput,
+            bytecodec::ErrorKind::UnexpectedEos => ErrorKind::ConnectionReset,
+            _ => f
+                .concrete_cause::<io::Error>()
+                .map_or(ErrorKind::Other, ErrorKind::from_io_error),
         };
         track!(kind.takes_over(f); original_error_kind).into()
     }
 }
+impl From<native_tls::Error> for Error {
+    fn from(f: native_tls::Error) -> Self {
+        ErrorKind::Tls.cause(f).into()
+    }
+}
 impl From<serdeconv::Error> for Error {
     fn from(f: serdeconv::Error) -> Self {
         ErrorKind::Other.takes_over(f).into()
     }
 }
+impl From<hdrhistogram::AdditionError> for Error {
+    fn from(f: hdrhistogram::AdditionError) -> Self {
+        ErrorKind::Other.cause(f).into()
+    }
+}
+impl From<hdrhistogram::serialization::V2DeflateSerializeError> for Error {
+    fn from(f: hdrhistogram::serialization::V2DeflateSerializeError) -> Self {
+        ErrorKind::Other.cause(f).into()
+    }
+}
+impl From<hdrhistogram::serialization::DeserializeError> for Error {
+    fn from(f: hdrhistogram::serialization::DeserializeError) -> Self {
+        ErrorKind::Other.cause(f).into()
+    }
+}
+impl From<base64::DecodeError> for Error {
+    fn from(f: base64::DecodeError) -> Self {
+        ErrorKind::Other.cause(f).into()
+    }
+}
 impl From<MonitorError<Error>> for Error {
     fn from(f: MonitorError<Error>) -> Self {
         f.unwrap_or_else(|| {
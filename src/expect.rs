@@ -0,0 +1,214 @@
+//! Assertions on responses.
+use httpcodec::Response;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/// Rules that a response must satisfy to be regarded as a success.
+///
+/// Rules are checked in order and the first violated one is reported.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expect {
+    /// Acceptable status codes (any status is accepted if empty).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub status: Vec<u16>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_contains: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_regex: Option<Pattern>,
+
+    /// Values that the JSON body must have at the given JSON pointers (e.g., `"/data/0/id"`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub json: BTreeMap<String, serde_json::Value>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_body_size: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_body_size: Option<u64>,
+}
+impl Expect {
+    pub fn check(&self, response: &Response<Vec<u8>>) -> Result<(), AssertionFailure> {
+        let status = response.status_code().as_u16();
+        if !self.status.is_empty() && !self.status.contains(&status) {
+            return Err(AssertionFailure::new(
+                Rule::Status,
+                format!("Unexpected status {} (expected: {:?})", status, self.status),
+            ));
+        }
+
+        let body = response.body();
+        let size = body.len() as u64;
+        if let Some(min) = self.min_body_size {
+            if size < min {
+                return Err(AssertionFailure::new(
+                    Rule::MinBodySize,
+                    format!("Body size {} is less than {}", size, min),
+                ));
+            }
+        }
+        if let Some(max) = self.max_body_size {
+            if size > max {
+                return Err(AssertionFailure::new(
+                    Rule::MaxBodySize,
+                    format!("Body size {} is greater than {}", size, max),
+                ));
+            }
+        }
+
+        if self.body_contains.is_none() && self.body_regex.is_none() && self.json.is_empty() {
+            return Ok(());
+        }
+        let text = String::from_utf8_lossy(body);
+        if let Some(s) = &self.body_contains {
+            if !text.contains(s.as_str()) {
+                return Err(AssertionFailure::new(
+                    Rule::BodyContains,
+                    format!("Body does not contain {:?}", s),
+                ));
+            }
+        }
+        if let Some(pattern) = &self.body_regex {
+            if !pattern.0.is_match(&text) {
+                return Err(AssertionFailure::new(
+                    Rule::BodyRegex,
+                    format!("Body does not match /{}/", pattern.0),
+                ));
+            }
+        }
+        if !self.json.is_empty() {
+            let json = serde_json::from_slice::<serde_json::Value>(body).map_err(|e| {
+                AssertionFailure::new(Rule::Json, format!("Body is not a JSON: {}", e))
+            })?;
+            for (pointer, expected) in &self.json {
+                let actual = json.pointer(pointer);
+                if actual != Some(expected) {
+                    return Err(AssertionFailure::new(
+                        Rule::Json,
+                        format!(
+                            "Unexpected value at {:?}: {} (expected: {})",
+                            pointer,
+                            actual.map_or_else(|| "none".to_owned(), |v| v.to_string()),
+                            expected
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A regular expression which is (de)serialized as a string.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Pattern(pub Regex);
+impl TryFrom<String> for Pattern {
+    type Error = regex::Error;
+    fn try_from(f: String) -> Result<Self, Self::Error> {
+        Regex::new(&f).map(Pattern)
+    }
+}
+impl From<Pattern> for String {
+    fn from(f: Pattern) -> Self {
+        f.0.as_str().to_owned()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rule {
+    Status,
+    BodyContains,
+    BodyRegex,
+    Json,
+    MinBodySize,
+    MaxBodySize,
+
+    /// A value of a flow step could not be extracted from the response.
+    Extract,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssertionFailure {
+    pub rule: Rule,
+    pub message: String,
+}
+impl AssertionFailure {
+    fn new(rule: Rule, message: String) -> Self {
+        AssertionFailure { rule, message }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn response(status: u16, body: &str) -> Response<Vec<u8>> {
+        Response::new(
+            httpcodec::HttpVersion::V1_1,
+            httpcodec::StatusCode::new(status).unwrap(),
+            httpcodec::ReasonPhrase::new("").unwrap(),
+            body.as_bytes().to_vec(),
+        )
+    }
+
+    fn violated(expect: &str, response: &Response<Vec<u8>>) -> Option<Rule> {
+        let expect: Expect = serde_json::from_str(expect).unwrap();
+        expect.check(response).err().map(|f| f.rule)
+    }
+
+    #[test]
+    fn status_rule_works() {
+        let expect = r#"{"status": [200, 204]}"#;
+        assert_eq!(violated(expect, &response(204, "")), None);
+        assert_eq!(violated(expect, &response(500, "")), Some(Rule::Status));
+    }
+
+    #[test]
+    fn body_regex_rule_works() {
+        let expect = r#"{"body_regex": "^id=[0-9]+$"}"#;
+        assert_eq!(violated(expect, &response(200, "id=42")), None);
+        assert_eq!(
+            violated(expect, &response(200, "id=foo")),
+            Some(Rule::BodyRegex)
+        );
+    }
+
+    #[test]
+    fn json_rule_works() {
+        let expect = r#"{"json": {"/data/0/id": 1}}"#;
+        assert_eq!(
+            violated(expect, &response(200, r#"{"data": [{"id": 1}]}"#)),
+            None
+        );
+        assert_eq!(
+            violated(expect, &response(200, r#"{"data": [{"id": 2}]}"#)),
+            Some(Rule::Json)
+        );
+        assert_eq!(
+            violated(expect, &response(200, r#"{"data": []}"#)),
+            Some(Rule::Json)
+        );
+        assert_eq!(violated(expect, &response(200, "id=1")), Some(Rule::Json));
+    }
+
+    #[test]
+    fn body_size_rules_work() {
+        let expect = r#"{"min_body_size": 2, "max_body_size": 4}"#;
+        assert_eq!(violated(expect, &response(200, "ab")), None);
+        assert_eq!(violated(expect, &response(200, "abcd")), None);
+        assert_eq!(
+            violated(expect, &response(200, "a")),
+            Some(Rule::MinBodySize)
+        );
+        assert_eq!(
+            violated(expect, &response(200, "abcde")),
+            Some(Rule::MaxBodySize)
+        );
+    }
+}
@@ -0,0 +1,410 @@
+//! Scenario flows: ordered sequences of requests run by a single virtual user.
+//!
+//! Values extracted from the response of a step can be used in the later steps
+//! as `{{name}}` placeholders in the URL, headers and body.
+use crate::expect::{AssertionFailure, Pattern, Rule};
+use crate::request::{validate_header, Content, Request};
+use crate::template::{split_placeholder, RequestTemplate};
+use crate::{Error, ErrorKind, Result};
+use httpcodec::Response;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use std::sync::Arc;
+use url::Url;
+
+/// The format of flow files.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FlowFile {
+    /// Number of flow executions (the flows are used in turn).
+    #[serde(default)]
+    pub count: Option<usize>,
+
+    pub flows: Vec<Flow>,
+}
+impl FlowFile {
+    pub fn read_from<R: Read>(reader: R) -> Result<Self> {
+        let file: Self = track!(serdeconv::from_json_reader(reader))?;
+        for flow in &file.flows {
+            track!(flow.validate())?;
+        }
+        Ok(file)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Flow {
+    pub name: String,
+
+    /// Initial values of the variables.
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+
+    pub steps: Vec<Step>,
+}
+impl Flow {
+    /// Checks that every placeholder refers to an initial variable or a value extracted by an earlier step.
+    fn validate(&self) -> Result<()> {
+        track_assert!(
+            !self.steps.is_empty(),
+            ErrorKind::Other,
+            "No steps: {}",
+            self.name
+        );
+        let mut defined = self.variables.keys().cloned().collect::<BTreeSet<_>>();
+        for step in &self.steps {
+            let r = &step.request;
+            track_assert!(
+                r.start_time.is_none(),
+                ErrorKind::Other,
+                "`start_time` cannot be used in flows: the step {:?} of the flow {:?}",
+                step.name,
+                self.name
+            );
+            let mut texts = vec![r.url.as_str()];
+            texts.extend(r.headers.values().map(|v| v.as_str()));
+            if let Some(Content::Text(text)) = &r.content {
+                texts.push(text);
+            }
+            for text in texts {
+                for name in track!(placeholders(text))? {
+                    track_assert!(
+                        defined.contains(name),
+                        ErrorKind::Other,
+                        "Undefined variable {:?} in the step {:?} of the flow {:?}",
+                        name,
+                        step.name,
+                        self.name
+                    );
+                }
+            }
+            defined.extend(step.extract.keys().cloned());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    pub name: String,
+
+    /// The request of the step, which must not have `start_time`.
+    pub request: RequestTemplate,
+
+    /// Values to extract from the response.
+    #[serde(default)]
+    pub extract: BTreeMap<String, Extractor>,
+}
+
+/// How to extract a value from a response (e.g., `{"json": "/token"}`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Extractor {
+    /// The value at the JSON pointer in the body (strings are used without quotes).
+    Json(String),
+
+    /// The value of the header.
+    Header(String),
+
+    /// The first capture group (or the whole match if there is no group) of the regular expression in the body.
+    Regex(Pattern),
+}
+impl Extractor {
+    pub fn extract(&self, response: &Response<Vec<u8>>) -> Option<String> {
+        match self {
+            Extractor::Json(pointer) => {
+                let json = serde_json::from_slice::<serde_json::Value>(response.body()).ok()?;
+                match json.pointer(pointer)? {
+                    serde_json::Value::String(s) => Some(s.clone()),
+                    value => Some(value.to_string()),
+                }
+            }
+            Extractor::Header(name) => response.header().get_field(name).map(|v| v.to_owned()),
+            Extractor::Regex(pattern) => {
+                let text = String::from_utf8_lossy(response.body());
+                let captures = pattern.0.captures(&text)?;
+                captures
+                    .get(1)
+                    .or_else(|| captures.get(0))
+                    .map(|m| m.as_str().to_owned())
+            }
+        }
+    }
+}
+
+/// Extracts all the values, or reports the first one that is missing.
+pub fn extract_values(
+    extractors: &BTreeMap<String, Extractor>,
+    response: &Response<Vec<u8>>,
+) -> std::result::Result<BTreeMap<String, String>, AssertionFailure> {
+    let mut values = BTreeMap::new();
+    for (name, extractor) in extractors {
+        let value = extractor
+            .extract(response)
+            .ok_or_else(|| AssertionFailure {
+                rule: Rule::Extract,
+                message: format!("Could not extract {:?} ({:?})", name, extractor),
+            })?;
+        values.insert(name.clone(), value);
+    }
+    Ok(values)
+}
+
+/// An iterator that yields a session per flow execution.
+#[derive(Debug)]
+pub struct FlowGenerator {
+    flows: Vec<Arc<Flow>>,
+    remaining: Option<usize>,
+    next: usize,
+}
+impl FlowGenerator {
+    /// Makes a new generator.
+    ///
+    /// If `count` is `None`, the count of `file` is used, and if it is also `None`,
+    /// each flow is executed once unless `unlimited` is `true`.
+    pub fn new(file: FlowFile, count: Option<usize>, unlimited: bool) -> Result<Self> {
+        track_assert!(!file.flows.is_empty(), ErrorKind::Other, "No flows");
+        for flow in &file.flows {
+            track!(flow.validate())?;
+        }
+        let remaining = count.or(file.count).or(if unlimited {
+            None
+        } else {
+            Some(file.flows.len())
+        });
+        Ok(FlowGenerator {
+            flows: file.flows.into_iter().map(Arc::new).collect(),
+            remaining,
+            next: 0,
+        })
+    }
+
+    /// Returns the total number of the requests of the remaining sessions (`None` if unlimited).
+    pub fn request_count(&self) -> Option<usize> {
+        let remaining = self.remaining?;
+        let n = self.flows.len();
+        let steps = |i: usize| self.flows[(self.next + i) % n].steps.len();
+        let cycle = (0..n).map(steps).sum::<usize>();
+        Some(remaining / n * cycle + (0..remaining % n).map(steps).sum::<usize>())
+    }
+}
+impl Iterator for FlowGenerator {
+    type Item = Session;
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(remaining) = self.remaining.as_mut() {
+            if *remaining == 0 {
+                return None;
+            }
+            *remaining -= 1;
+        }
+        let flow = Arc::clone(&self.flows[self.next % self.flows.len()]);
+        self.next += 1;
+        Some(Session::new(flow))
+    }
+}
+
+/// An execution of a flow.
+#[derive(Debug)]
+pub struct Session {
+    flow: Arc<Flow>,
+    step: usize,
+    variables: BTreeMap<String, String>,
+
+    /// The names of the variables extracted from responses.
+    extracted: BTreeSet<String>,
+}
+impl Session {
+    pub fn new(flow: Arc<Flow>) -> Self {
+        Session {
+            variables: flow.variables.clone(),
+            extracted: BTreeSet::new(),
+            flow,
+            step: 0,
+        }
+    }
+    pub fn flow_name(&self) -> &str {
+        &self.flow.name
+    }
+
+    /// Returns the number of the steps that have not finished yet (including the current one).
+    pub fn remaining_steps(&self) -> usize {
+        self.flow.steps.len().saturating_sub(self.step)
+    }
+
+    /// Returns the step to execute next (`None` if the session has finished).
+    pub fn step(&self) -> Option<&Step> {
+        self.flow.steps.get(self.step)
+    }
+
+    /// Expands the request of the current step.
+    ///
+    /// The extracted values are percent-encoded in the URL.
+    pub fn request(&self) -> Result<Request> {
+        let step = track_assert_some!(self.step(), ErrorKind::Other, "Finished session");
+        let t = &step.request;
+        let url = self.render(&t.url, true);
+        let url = track!(Url::parse(&url).map_err(Error::from); url)?;
+        let mut headers = BTreeMap::new();
+        for (name, value) in &t.headers {
+            let rendered = self.render(value, false);
+            if rendered != *value {
+                track!(validate_header(name, &rendered))?;
+            }
+            headers.insert(name.clone(), rendered);
+        }
+        Ok(Request {
+            method: t.method.clone(),
+            url,
+            content: t.content.as_ref().map(|c| match c {
+                Content::Size(size) => Content::Size(*size),
+                Content::Text(text) => Content::Text(self.render(text, false)),
+            }),
+            timeout: t.timeout,
+            start_time: None,
+            headers,
+            expect: t.expect.clone(),
+            name: t.name.clone(),
+            tags: t.tags.clone(),
+        })
+    }
+
+    /// Stores the values extracted from the response of the current step and moves to the next step.
+    pub fn advance(&mut self, values: BTreeMap<String, String>) {
+        self.extracted.extend(values.keys().cloned());
+        self.variables.extend(values);
+        self.step += 1;
+    }
+
+    fn render(&self, text: &str, is_url: bool) -> String {
+        let mut rendered = String::new();
+        let mut rest = text;
+        // NOTE: The placeholders have been checked by `Flow::validate`.
+        while let Some((before, name, after)) = split_placeholder(rest).expect("Never fails") {
+            rendered.push_str(before);
+            match self.variables.get(name) {
+                Some(value) if is_url && self.extracted.contains(name) => {
+                    rendered.push_str(&percent_encode(value))
+                }
+                Some(value) => rendered.push_str(value),
+                None => rendered.push_str(&rest[before.len()..rest.len() - after.len()]),
+            }
+            rest = after;
+        }
+        rendered.push_str(rest);
+        rendered
+    }
+}
+
+/// Encodes all the characters except the unreserved ones of RFC 3986.
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::new();
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+fn placeholders(text: &str) -> Result<Vec<&str>> {
+    let mut names = Vec::new();
+    let mut rest = text;
+    while let Some((_, name, after)) = track!(split_placeholder(rest))? {
+        names.push(name);
+        rest = after;
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn session_works() {
+        let file = FlowFile::read_from(
+            r#"{"flows": [{"name": "checkout", "variables": {"user": "alice"}, "steps": [
+                 {"name": "login", "request": {"method": "POST", "url": "http://localhost/login",
+                  "content": "{\"user\": \"{{user}}\"}"}, "extract": {"token": {"json": "/token"}}},
+                 {"name": "profile", "request": {"method": "GET", "url": "http://localhost/users/{{user}}",
+                  "headers": {"Authorization": "Bearer {{ token }}"}}}
+               ]}]}"#
+                .as_bytes(),
+        )
+        .unwrap();
+        let mut sessions = FlowGenerator::new(file, None, false).unwrap();
+        assert_eq!(sessions.request_count(), Some(2));
+
+        let mut session = sessions.next().unwrap();
+        assert!(sessions.next().is_none());
+        assert_eq!(session.step().unwrap().name, "login");
+        assert!(matches!(
+            session.request().unwrap().content,
+            Some(Content::Text(ref t)) if t == r#"{"user": "alice"}"#
+        ));
+
+        let response = Response::new(
+            httpcodec::HttpVersion::V1_1,
+            httpcodec::StatusCode::new(200).unwrap(),
+            httpcodec::ReasonPhrase::new("OK").unwrap(),
+            br#"{"token": "xyz"}"#.to_vec(),
+        );
+        let values = extract_values(&session.step().unwrap().extract, &response).unwrap();
+        session.advance(values);
+        let request = session.request().unwrap();
+        assert_eq!(request.url.as_str(), "http://localhost/users/alice");
+        assert_eq!(request.headers["Authorization"], "Bearer xyz");
+
+        let bytes = crate::http::build_request(&request, true).unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+        assert!(text.starts_with("GET /users/alice HTTP/1.1\r\n"));
+        assert!(text.contains("\r\nAuthorization: Bearer xyz\r\n"));
+
+        session.advance(BTreeMap::new());
+        assert!(session.step().is_none());
+    }
+
+    #[test]
+    fn extracted_values_are_percent_encoded_in_urls() {
+        let file = FlowFile::read_from(
+            r#"{"flows": [{"name": "f", "variables": {"dir": "a/b"}, "steps": [
+                 {"name": "s1", "request": {"method": "GET", "url": "http://localhost/"},
+                  "extract": {"id": {"json": "/id"}}},
+                 {"name": "s2", "request": {"method": "GET", "url": "http://localhost/{{dir}}/{{id}}",
+                  "content": "{{id}}"}}
+               ]}]}"#
+                .as_bytes(),
+        )
+        .unwrap();
+        let mut session = FlowGenerator::new(file, None, false)
+            .unwrap()
+            .next()
+            .unwrap();
+        let mut values = BTreeMap::new();
+        values.insert("id".to_owned(), "x y/z?".to_owned());
+        session.advance(values);
+        let request = session.request().unwrap();
+        assert_eq!(request.url.as_str(), "http://localhost/a/b/x%20y%2Fz%3F");
+        assert!(matches!(request.content, Some(Content::Text(ref t)) if t == "x y/z?"));
+    }
+
+    #[test]
+    fn invalid_steps_are_rejected() {
+        for request in [
+            r#"{"method": "GET", "url": "http://localhost/{{id}}"}"#,
+            r#"{"method": "GET", "url": "http://localhost/{{id"}"#,
+            r#"{"method": "GET", "url": "http://localhost/", "start_time": 1.0}"#,
+        ] {
+            let file = format!(
+                r#"{{"flows": [{{"name": "f", "steps": [{{"name": "s", "request": {}}}]}}]}}"#,
+                request
+            );
+            assert!(FlowFile::read_from(file.as_bytes()).is_err(), "{}", request);
+        }
+    }
+}
@@ -0,0 +1,348 @@
+//! HDR histograms of latencies and the HdrHistogram interval log format.
+//!
+//! Latencies are recorded in microseconds with three significant digits,
+//! so histograms of different runs can be added up without losing precision.
+use crate::run::{RequestResult, Seconds};
+use crate::summary;
+use crate::{Error, ErrorKind, Result};
+use base64::Engine;
+use fibers::time::timer;
+use futures::{Async, Future};
+use hdrhistogram::serialization::interval_log::{
+    IntervalLogIterator, IntervalLogWriterBuilder, LogEntry,
+};
+use hdrhistogram::serialization::{Deserializer, Serializer, V2DeflateSerializer};
+use hdrhistogram::Histogram;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant, SystemTime};
+use trackable::error::ErrorKindExt;
+
+const SIGNIFICANT_DIGITS: u8 = 3;
+
+/// Makes the max value column of interval logs milliseconds.
+const MAX_VALUE_DIVISOR: f64 = 1000.0;
+
+/// Tag of the corrected latency histograms in interval logs (the raw latency histograms are untagged).
+pub const CORRECTED_TAG: &str = "corrected";
+
+/// An auto-resizing histogram of latencies.
+///
+/// The exact minimum and maximum are kept alongside the histogram
+/// (except for histograms decoded from interval logs, which do not have them).
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    histogram: Histogram<u64>,
+    min: Option<Seconds>,
+    max: Option<Seconds>,
+}
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        LatencyHistogram::default()
+    }
+    pub fn record(&mut self, latency: Seconds) {
+        let micros = (latency.0 * 1_000_000.0).round() as u64;
+        self.histogram.record(micros).expect("Never fails");
+        self.min = Some(self.min.map_or(latency, |min| min.min(latency)));
+        self.max = Some(self.max.map_or(latency, |max| max.max(latency)));
+    }
+
+    /// Adds the values recorded in `other`.
+    pub fn add(&mut self, other: &Self) -> Result<()> {
+        if other.is_empty() {
+            return Ok(());
+        }
+        let (min, max) = if self.is_empty() {
+            (other.min(), other.max())
+        } else {
+            (self.min().min(other.min()), self.max().max(other.max()))
+        };
+        track!(self.histogram.add(&other.histogram).map_err(Error::from))?;
+        self.min = Some(min);
+        self.max = Some(max);
+        Ok(())
+    }
+    pub fn reset(&mut self) {
+        self.histogram.reset();
+        self.min = None;
+        self.max = None;
+    }
+    pub fn len(&self) -> u64 {
+        self.histogram.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.histogram.is_empty()
+    }
+    pub fn min(&self) -> Seconds {
+        self.min
+            .unwrap_or_else(|| from_micros(self.histogram.min()))
+    }
+    pub fn max(&self) -> Seconds {
+        self.max
+            .unwrap_or_else(|| from_micros(self.histogram.max()))
+    }
+    pub fn mean(&self) -> Seconds {
+        Seconds(self.histogram.mean() / 1_000_000.0)
+    }
+
+    /// Returns the unbiased variance (in seconds squared).
+    pub fn variance(&self) -> f64 {
+        summary::unbiased_variance(self.histogram.iter_recorded().map(|v| {
+            let value = from_micros(self.histogram.median_equivalent(v.value_iterated_to()));
+            (value, v.count_at_value())
+        }))
+    }
+
+    /// Returns the value at the given percentile (e.g., `99.9`).
+    pub fn percentile(&self, p: f64) -> Seconds {
+        from_micros(self.histogram.value_at_quantile(p / 100.0))
+    }
+
+    /// Encodes the histogram in the compressed V2 format used by interval logs.
+    pub fn to_base64(&self) -> Result<String> {
+        let mut bytes = Vec::new();
+        track!(V2DeflateSerializer::new()
+            .serialize(&self.histogram, &mut bytes)
+            .map_err(Error::from))?;
+        Ok(base64::engine::general_purpose::STANDARD.encode(bytes))
+    }
+    pub fn from_base64(encoded: &str) -> Result<Self> {
+        let bytes = track!(base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(Error::from))?;
+        let mut histogram: Histogram<u64> = track!(Deserializer::new()
+            .deserialize(&mut &bytes[..])
+            .map_err(Error::from))?;
+        histogram.auto(true);
+        Ok(LatencyHistogram {
+            histogram,
+            min: None,
+            max: None,
+        })
+    }
+}
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            histogram: Histogram::new(SIGNIFICANT_DIGITS).expect("Never fails"),
+            min: None,
+            max: None,
+        }
+    }
+}
+
+fn from_micros(micros: u64) -> Seconds {
+    Seconds(micros as f64 / 1_000_000.0)
+}
+
+/// A histogram read from an interval log.
+#[derive(Debug, Clone)]
+pub struct Interval {
+    /// Start of the interval in seconds since the `BaseTime` of the log (or the epoch if it has none).
+    pub start: Seconds,
+    pub duration: Seconds,
+    pub tag: Option<String>,
+    pub histogram: LatencyHistogram,
+}
+
+/// Reads the interval histograms of an interval log.
+pub fn read_interval_log<R: Read>(mut reader: R) -> Result<Vec<Interval>> {
+    let mut buf = Vec::new();
+    track!(reader.read_to_end(&mut buf).map_err(Error::from))?;
+    let mut intervals = Vec::new();
+    for entry in IntervalLogIterator::new(&buf) {
+        let entry =
+            track!(entry.map_err(|e| Error::from(ErrorKind::Other.cause(format!("{:?}", e)))))?;
+        if let LogEntry::Interval(h) = entry {
+            intervals.push(Interval {
+                start: h.start_timestamp().into(),
+                duration: h.duration().into(),
+                tag: h.tag().map(|t| t.as_str().to_owned()),
+                histogram: track!(LatencyHistogram::from_base64(h.encoded_histogram()))?,
+            });
+        }
+    }
+    Ok(intervals)
+}
+
+/// Records the latencies of the results and writes them as an interval log.
+///
+/// Each interval has a histogram of the raw latencies and one of the corrected latencies
+/// (tagged with `CORRECTED_TAG`).
+pub(crate) struct IntervalLogRecorder {
+    writer: Box<dyn Write + Send>,
+    interval: Duration,
+    timer: timer::Timeout,
+    bench_start: Instant,
+    interval_start: Duration,
+    latency: LatencyHistogram,
+    corrected_latency: LatencyHistogram,
+}
+impl IntervalLogRecorder {
+    pub fn new(
+        mut writer: Box<dyn Write + Send>,
+        interval: Duration,
+        bench_start: Instant,
+    ) -> Result<Self> {
+        let base_time = SystemTime::now() - bench_start.elapsed();
+        let mut serializer = V2DeflateSerializer::new();
+        track!(IntervalLogWriterBuilder::new()
+            .add_comment(&format!("Logged with hb {}", env!("CARGO_PKG_VERSION")))
+            .add_comment("Latencies are recorded in microseconds")
+            .with_start_time(base_time)
+            .with_base_time(base_time)
+            .with_max_value_divisor(MAX_VALUE_DIVISOR)
+            .begin_log_with(&mut writer, &mut serializer)
+            .map_err(Error::from))?;
+        Ok(IntervalLogRecorder {
+            writer,
+            interval,
+            timer: timer::timeout(interval),
+            bench_start,
+            interval_start: Duration::from_secs(0),
+            latency: LatencyHistogram::new(),
+            corrected_latency: LatencyHistogram::new(),
+        })
+    }
+
+    pub fn record(&mut self, result: &RequestResult) {
+        self.latency.record(result.elapsed());
+        self.corrected_latency.record(result.corrected_elapsed());
+    }
+
+    /// Writes the histograms of the current interval if it has elapsed.
+    pub fn poll(&mut self) -> Result<()> {
+        while let Async::Ready(()) = track!(self.timer.poll().map_err(Error::from))? {
+            track!(self.write_interval())?;
+            self.timer = timer::timeout(self.interval);
+        }
+        Ok(())
+    }
+
+    /// Writes the histograms of the last (partial) interval.
+    pub fn finish(&mut self) -> Result<()> {
+        track!(self.write_interval())?;
+        track!(self.writer.flush().map_err(Error::from))?;
+        Ok(())
+    }
+
+    fn write_interval(&mut self) -> Result<()> {
+        let end = self.bench_start.elapsed();
+        let start = self.interval_start.as_secs_f64();
+        let duration = (end - self.interval_start).as_secs_f64();
+        let histograms = [
+            (None, &self.latency),
+            (Some(CORRECTED_TAG), &self.corrected_latency),
+        ];
+        for (tag, histogram) in histograms.iter() {
+            let tag = tag.map_or_else(String::new, |t| format!("Tag={},", t));
+            let max = histogram.max().0 * 1_000_000.0 / MAX_VALUE_DIVISOR;
+            let encoded = track!(histogram.to_base64())?;
+            track!(writeln!(
+                self.writer,
+                "{}{:.3},{:.3},{:.3},{}",
+                tag, start, duration, max, encoded
+            )
+            .map_err(Error::from))?;
+        }
+        self.latency.reset();
+        self.corrected_latency.reset();
+        self.interval_start = end;
+        Ok(())
+    }
+}
+impl std::fmt::Debug for IntervalLogRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("IntervalLogRecorder")
+            .field("interval", &self.interval)
+            .field("interval_start", &self.interval_start)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn interval_log_recorder_works() {
+        let buf = SharedBuf::default();
+        let mut recorder = IntervalLogRecorder::new(
+            Box::new(buf.clone()),
+            Duration::from_secs(60),
+            Instant::now(),
+        )
+        .unwrap();
+        for i in 1..=100 {
+            let mut result = RequestResult::ok_for_test(i, 1.0, i as f64 / 1000.0);
+            if let RequestResult::Ok {
+                scheduled_start_time,
+                ..
+            } = &mut result
+            {
+                *scheduled_start_time = Some(Seconds(0.0));
+            }
+            recorder.record(&result);
+        }
+        recorder.finish().unwrap();
+
+        let intervals = read_interval_log(&buf.0.lock().unwrap()[..]).unwrap();
+        assert_eq!(intervals.len(), 2);
+        assert_eq!(intervals[0].tag, None);
+        assert_eq!(intervals[0].histogram.len(), 100);
+        assert!((intervals[0].histogram.max().0 - 0.1).abs() < 0.0001);
+        assert_eq!(intervals[1].tag.as_deref(), Some(CORRECTED_TAG));
+        assert!((intervals[1].histogram.min().0 - 1.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn exact_min_max_works() {
+        let mut histogram = LatencyHistogram::new();
+        histogram.record(Seconds(0.1234567));
+        histogram.record(Seconds(2.3456789));
+        assert_eq!(histogram.min(), Seconds(0.1234567));
+        assert_eq!(histogram.max(), Seconds(2.3456789));
+
+        let mut other = LatencyHistogram::new();
+        other.record(Seconds(0.05));
+        other.add(&histogram).unwrap();
+        assert_eq!(other.min(), Seconds(0.05));
+        assert_eq!(other.max(), Seconds(2.3456789));
+    }
+
+    #[test]
+    fn interval_log_round_trip_works() {
+        let mut histogram = LatencyHistogram::new();
+        for i in 1..=1000 {
+            histogram.record(Seconds(i as f64 / 1000.0));
+        }
+        let encoded = histogram.to_base64().unwrap();
+        let log = format!(
+            "#[BaseTime: 1500000000.000 (seconds since epoch)]\n\
+             0.000,1.000,1.000,{}\nTag={},0.000,1.000,1.000,{}\n",
+            encoded, CORRECTED_TAG, encoded
+        );
+        let intervals = read_interval_log(log.as_bytes()).unwrap();
+        assert_eq!(intervals.len(), 2);
+        assert_eq!(intervals[1].tag.as_deref(), Some(CORRECTED_TAG));
+
+        let mut merged = intervals[0].histogram.clone();
+        merged.add(&intervals[1].histogram).unwrap();
+        assert_eq!(merged.len(), 2000);
+        assert_eq!(merged.min(), Seconds(0.001));
+        assert!((merged.percentile(50.0).0 - 0.5).abs() < 0.0005);
+        assert!((merged.percentile(99.0).0 - 0.99).abs() < 0.001);
+        assert!((merged.mean().0 - 0.5005).abs() < 0.0005);
+    }
+}
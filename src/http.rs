@@ -0,0 +1,778 @@
+//! A minimal HTTP/1.1 client with a pool of keep-alive connections.
+//!
+//! This replaces `fibers_http_client`, whose API only issues GET, HEAD, DELETE, PUT and POST
+//! requests over plain TCP and does not expose the time taken by each phase of a request.
+use crate::request::{self, validate_header, Method, Request};
+use crate::run::{Phases, Seconds};
+use crate::tls::TlsConnector;
+use crate::{Error, ErrorKind, Result};
+use bytecodec::bytes::{BytesEncoder, RemainingBytesDecoder};
+use bytecodec::io::{BufferedIo, IoDecodeExt, IoEncodeExt};
+use bytecodec::{Decode, Encode};
+use fibers::net::TcpStream;
+use fibers::sync::oneshot;
+use fibers::time::timer::TimerExt;
+use futures::future::Either;
+use futures::{Async, Future, Poll};
+use httpcodec::{
+    BodyDecode, BodyDecoder, HttpVersion, NoBodyDecoder, RequestTarget, Response, ResponseDecoder,
+};
+use native_tls::TlsStream;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use trackable::error::ErrorKindExt;
+use url::{Host, Origin, Position, Url};
+
+const BUF_SIZE: usize = 4096;
+
+/// How long an idle connection is kept in `ConnectionPool`.
+const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+type RequestEncoderImpl = BytesEncoder<Vec<u8>>;
+
+pub type ResponseFuture =
+    Box<dyn Future<Item = (Response<Vec<u8>>, Phases), Error = Error> + Send + 'static>;
+
+/// Sends a request over a newly established connection which is closed after the response is received.
+///
+/// If `tls` is `None`, HTTPS requests use `TlsConnector::shared_default`.
+pub fn send(
+    request: &Request,
+    tls: Option<&TlsConnector>,
+    timeout: Option<Duration>,
+) -> Result<ResponseFuture> {
+    let message = track!(build_request(request, false))?;
+    let is_head = request.method == Method::Head;
+    let connect = track!(connect(&request.url, tls))?;
+    let future =
+        send_over(connect, message, is_head).map(|(response, phases, _)| (response, phases));
+    Ok(with_timeout(Box::new(future), timeout))
+}
+
+/// A connection to an HTTP or HTTPS server.
+#[derive(Debug)]
+enum Stream {
+    Tcp(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.read(buf),
+            Stream::Tls(s) => s.read(buf),
+        }
+    }
+}
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.write(buf),
+            Stream::Tls(s) => s.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.flush(),
+            Stream::Tls(s) => s.flush(),
+        }
+    }
+}
+
+/// A stream and the time taken to establish it.
+struct Connected {
+    stream: Stream,
+    dns: Duration,
+    connect: Duration,
+    tls: Option<Duration>,
+}
+
+type ConnectFuture = Box<dyn Future<Item = Connected, Error = Error> + Send + 'static>;
+
+fn connect(url: &Url, tls: Option<&TlsConnector>) -> Result<ConnectFuture> {
+    let tls = if url.scheme() == "https" {
+        Some(match tls {
+            Some(tls) => tls.clone(),
+            None => track!(TlsConnector::shared_default())?,
+        })
+    } else {
+        None
+    };
+    let dns_start = Instant::now();
+    let connect = resolve(url).and_then(move |addr| {
+        let dns = dns_start.elapsed();
+        let connect_start = Instant::now();
+        TcpStream::connect(addr)
+            .map_err(move |e| track!(Error::from(e); addr))
+            .map(move |stream| (stream, dns, connect_start.elapsed()))
+    });
+    if let Some(tls) = tls {
+        let domain = url.host_str().unwrap_or_default().to_owned();
+        Ok(Box::new(connect.and_then(move |(stream, dns, connect)| {
+            let tls_start = Instant::now();
+            tls.connect(&domain, stream).map(move |stream| Connected {
+                stream: Stream::Tls(Box::new(stream)),
+                dns,
+                connect,
+                tls: Some(tls_start.elapsed()),
+            })
+        })))
+    } else {
+        Ok(Box::new(connect.map(move |(stream, dns, connect)| {
+            Connected {
+                stream: Stream::Tcp(stream),
+                dns,
+                connect,
+                tls: None,
+            }
+        })))
+    }
+}
+
+type ExchangedFuture = Box<
+    dyn Future<Item = (Response<Vec<u8>>, Phases, BufferedIo<Stream>), Error = Error>
+        + Send
+        + 'static,
+>;
+
+/// Sends an encoded request over the connection established by `connect`.
+fn send_over(connect: ConnectFuture, message: Vec<u8>, is_head: bool) -> ExchangedFuture {
+    Box::new(connect.and_then(move |c| {
+        let Connected {
+            stream,
+            dns,
+            connect,
+            tls,
+        } = c;
+        let mut encoder = BytesEncoder::new();
+        let started = track!(encoder.start_encoding(message).map_err(Error::from));
+        futures::done(started).and_then(move |()| {
+            exchange(stream, encoder, is_head).map(move |(response, timings, io)| {
+                (response, timings.to_phases(dns, connect, tls), io)
+            })
+        })
+    }))
+}
+
+type ResolveFuture = Box<dyn Future<Item = SocketAddr, Error = Error> + Send + 'static>;
+
+/// Number of the threads which resolve domain names (see `resolve`).
+const RESOLVER_THREADS: usize = 4;
+
+type ResolveJob = (Url, oneshot::Monitored<SocketAddr, Error>);
+
+/// Resolves the address of the host of `url`.
+///
+/// Since the lookup blocks, domain names are resolved by a fixed set of threads so as not to block the fiber.
+fn resolve(url: &Url) -> ResolveFuture {
+    static RESOLVER: OnceLock<mpsc::Sender<ResolveJob>> = OnceLock::new();
+    if let Some(Host::Domain(_)) = url.host() {
+        let resolver = RESOLVER.get_or_init(|| {
+            let (tx, rx) = mpsc::channel::<ResolveJob>();
+            let rx = Arc::new(Mutex::new(rx));
+            for _ in 0..RESOLVER_THREADS {
+                let rx = Arc::clone(&rx);
+                thread::spawn(move || loop {
+                    let job = match rx.lock() {
+                        Ok(rx) => rx.recv(),
+                        Err(_) => return,
+                    };
+                    match job {
+                        Ok((url, monitored)) => monitored.exit(track!(request::resolve(&url))),
+                        Err(_) => return,
+                    }
+                });
+            }
+            tx
+        });
+        let (monitored, monitor) = oneshot::monitor();
+        if resolver.send((url.clone(), monitored)).is_err() {
+            return Box::new(futures::failed(track!(Error::from(
+                ErrorKind::Other.cause("Resolver threads are down")
+            ))));
+        }
+        Box::new(monitor.map_err(|e| track!(Error::from(e))))
+    } else {
+        Box::new(futures::done(track!(request::resolve(url))))
+    }
+}
+
+/// A pool of keep-alive connections shared by the clients of a run.
+///
+/// The connections are keyed by the origin (i.e., scheme, host and port) of the request URLs.
+#[derive(Debug, Clone)]
+pub struct ConnectionPool {
+    inner: Arc<Mutex<PoolInner>>,
+}
+impl ConnectionPool {
+    /// Makes a new `ConnectionPool` instance which holds at most `max_size` connections
+    /// (including the ones in use).
+    pub fn new(max_size: usize) -> Self {
+        let inner = PoolInner {
+            max_size,
+            size: 0,
+            idle: HashMap::new(),
+        };
+        ConnectionPool {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    /// Sends a request over an idle connection to its origin, or a new one if there is none.
+    ///
+    /// The connection is returned to the pool after the response is received unless
+    /// the server is going to close it.
+    /// If `tls` is `None`, HTTPS requests use `TlsConnector::shared_default`.
+    pub fn send(
+        &self,
+        request: &Request,
+        tls: Option<&TlsConnector>,
+        timeout: Option<Duration>,
+    ) -> Result<ResponseFuture> {
+        let message = track!(build_request(request, true))?;
+        let is_head = request.method == Method::Head;
+        let (lease, stream) = track!(self.acquire(request.url.origin()))?;
+        let future = match stream {
+            Some(stream) => {
+                let tls_time = match stream {
+                    Stream::Tcp(_) => None,
+                    Stream::Tls(_) => Some(Duration::default()),
+                };
+                let connected = Box::new(futures::finished(Connected {
+                    stream,
+                    dns: Duration::default(),
+                    connect: Duration::default(),
+                    tls: tls_time,
+                }));
+                let url = request.url.clone();
+                let tls = tls.cloned();
+                let retry = message.clone();
+                let future = send_over(connected, message, is_head).or_else(move |e| {
+                    // NOTE: The server may have closed the idle connection (e.g., by its keep-alive timeout),
+                    // so the request is sent once again over a new connection.
+                    if *e.kind() != ErrorKind::ConnectionReset {
+                        return Either::A(futures::failed(e));
+                    }
+                    log::debug!("Retries a request over a new connection: {}", e);
+                    match track!(connect(&url, tls.as_ref())) {
+                        Ok(connect) => Either::B(send_over(connect, retry, is_head)),
+                        Err(e) => Either::A(futures::failed(e)),
+                    }
+                });
+                Box::new(future) as ExchangedFuture
+            }
+            None => send_over(track!(connect(&request.url, tls))?, message, is_head),
+        };
+        let future = future.map(move |(response, phases, io)| {
+            if is_reusable(&response, &io, is_head) {
+                lease.reuse(io.into_stream());
+            }
+            (response, phases)
+        });
+        Ok(with_timeout(Box::new(future), timeout))
+    }
+
+    fn acquire(&self, origin: Origin) -> Result<(Lease, Option<Stream>)> {
+        let mut inner = track!(self.inner.lock().map_err(Error::from))?;
+        let stream = inner.lend(&origin);
+        if stream.is_none() {
+            if inner.size == inner.max_size {
+                track_assert!(
+                    inner.discard_oldest(),
+                    ErrorKind::PoolExhausted,
+                    "Max connection pool size reached: {}",
+                    inner.max_size
+                );
+            }
+            inner.size += 1;
+        }
+        let lease = Lease {
+            pool: self.inner.clone(),
+            origin,
+            reused: false,
+        };
+        Ok((lease, stream))
+    }
+}
+
+#[derive(Debug)]
+struct PoolInner {
+    max_size: usize,
+
+    /// The number of the idle and in-use connections.
+    size: usize,
+
+    /// The idle connections and the times when they were returned (in that order).
+    idle: HashMap<Origin, Vec<(Stream, Instant)>>,
+}
+impl PoolInner {
+    fn lend(&mut self, origin: &Origin) -> Option<Stream> {
+        let idle = self.idle.get_mut(origin)?;
+        let expired = idle
+            .iter()
+            .take_while(|(_, returned)| returned.elapsed() >= KEEPALIVE_TIMEOUT)
+            .count();
+        idle.drain(..expired);
+        self.size -= expired;
+        idle.pop().map(|(stream, _)| stream)
+    }
+
+    fn discard_oldest(&mut self) -> bool {
+        let oldest = self
+            .idle
+            .iter()
+            .filter_map(|(origin, idle)| Some((idle.first()?.1, origin)))
+            .min_by_key(|(returned, _)| *returned)
+            .map(|(_, origin)| origin.clone());
+        if let Some(origin) = oldest {
+            self.idle.get_mut(&origin).expect("Never fails").remove(0);
+            self.size -= 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A slot of `ConnectionPool`, which is freed when dropped unless its connection is reused.
+struct Lease {
+    pool: Arc<Mutex<PoolInner>>,
+    origin: Origin,
+    reused: bool,
+}
+impl Lease {
+    fn reuse(mut self, stream: Stream) {
+        if let Ok(mut inner) = self.pool.lock() {
+            let idle = inner.idle.entry(self.origin.clone()).or_default();
+            idle.push((stream, Instant::now()));
+            self.reused = true;
+        }
+    }
+}
+impl Drop for Lease {
+    fn drop(&mut self) {
+        if !self.reused {
+            if let Ok(mut inner) = self.pool.lock() {
+                inner.size -= 1;
+            }
+        }
+    }
+}
+
+fn is_reusable<S>(response: &Response<Vec<u8>>, io: &BufferedIo<S>, is_head: bool) -> bool {
+    let header = response.header();
+    let close = header
+        .get_field("Connection")
+        .is_some_and(|v| v.eq_ignore_ascii_case("close"));
+    let delimited = is_head
+        || header.get_field("Content-Length").is_some()
+        || header.get_field("Transfer-Encoding").is_some();
+    response.http_version() == HttpVersion::V1_1
+        && !close
+        && delimited
+        && !io.is_eos()
+        && io.read_buf_ref().is_empty()
+}
+
+fn with_timeout(future: ResponseFuture, timeout: Option<Duration>) -> ResponseFuture {
+    if let Some(timeout) = timeout {
+        Box::new(
+            future.timeout_after(timeout).map_err(|e| {
+                e.unwrap_or_else(|| ErrorKind::Timeout.cause("Request timed out").into())
+            }),
+        )
+    } else {
+        future
+    }
+}
+
+/// Serializes `request` as an HTTP/1.1 message.
+///
+/// The header fields are written as is after being checked by `validate_header`
+/// (unlike `httpcodec::HeaderField::new`, it accepts spaces in the values).
+pub(crate) fn build_request(request: &Request, keep_alive: bool) -> Result<Vec<u8>> {
+    let url = &request.url;
+    let method = track!(httpcodec::Method::new(request.method.as_str())
+        .map_err(|e| Error::from(ErrorKind::InvalidInput.cause(e))))?;
+    let target = track!(RequestTarget::new(&url[Position::BeforePath..])
+        .map_err(|e| Error::from(ErrorKind::InvalidInput.cause(e))); url)?;
+    let body = match request.method {
+        Method::Get | Method::Head | Method::Delete => Vec::new(),
+        _ => request
+            .content
+            .as_ref()
+            .map_or(Vec::new(), |c| c.to_bytes()),
+    };
+
+    let mut fields = Vec::new();
+    if !request
+        .headers
+        .keys()
+        .any(|k| k.eq_ignore_ascii_case("Host"))
+    {
+        fields.push(("Host", &url[Position::BeforeHost..Position::AfterPort]));
+    }
+    fields.extend(
+        request
+            .headers
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str())),
+    );
+    let content_length = body.len().to_string();
+    fields.push(("Content-Length", &content_length));
+    if !keep_alive {
+        fields.push(("Connection", "close"));
+    }
+
+    let mut message = format!("{} {} HTTP/1.1\r\n", method.as_str(), target.as_str());
+    for (name, value) in fields {
+        track!(validate_header(name, value))?;
+        message.push_str(name);
+        message.push_str(": ");
+        message.push_str(value);
+        message.push_str("\r\n");
+    }
+    message.push_str("\r\n");
+    let mut message = message.into_bytes();
+    message.extend_from_slice(&body);
+    Ok(message)
+}
+
+type ExchangeFuture<S> = Box<
+    dyn Future<Item = (Response<Vec<u8>>, ExchangeTimings, BufferedIo<S>), Error = Error>
+        + Send
+        + 'static,
+>;
+
+/// Sends an encoded request and receives its response over `stream`.
+///
+/// The stream is returned with the response so that the connection can be reused.
+fn exchange<S>(stream: S, encoder: RequestEncoderImpl, is_head: bool) -> ExchangeFuture<S>
+where
+    S: Read + Write + Send + 'static,
+{
+    if is_head {
+        let decoder = ResponseDecoder::new(NoBodyDecoder);
+        Box::new(
+            Exchange::new(stream, encoder, decoder)
+                .map(|(response, timings, io)| (response.map_body(|()| Vec::new()), timings, io)),
+        )
+    } else {
+        let decoder = ResponseDecoder::new(BodyDecoder::new(RemainingBytesDecoder::new()));
+        Box::new(Exchange::new(stream, encoder, decoder))
+    }
+}
+
+struct ExchangeTimings {
+    ttfb: Duration,
+    transfer: Duration,
+}
+impl ExchangeTimings {
+    fn to_phases(&self, dns: Duration, connect: Duration, tls: Option<Duration>) -> Phases {
+        Phases {
+            dns: dns.into(),
+            connect: connect.into(),
+            tls: tls.map(Seconds::from),
+            ttfb: self.ttfb.into(),
+            transfer: self.transfer.into(),
+        }
+    }
+}
+
+struct Exchange<S, D> {
+    stream: Option<BufferedIo<S>>,
+    encoder: RequestEncoderImpl,
+    decoder: ResponseDecoder<D>,
+    start_time: Instant,
+    first_byte_time: Option<Instant>,
+}
+impl<S: Read + Write, D: BodyDecode> Exchange<S, D> {
+    fn new(stream: S, encoder: RequestEncoderImpl, decoder: ResponseDecoder<D>) -> Self {
+        Exchange {
+            stream: Some(BufferedIo::new(stream, BUF_SIZE, BUF_SIZE)),
+            encoder,
+            decoder,
+            start_time: Instant::now(),
+            first_byte_time: None,
+        }
+    }
+}
+impl<S: Read + Write, D: BodyDecode> Future for Exchange<S, D> {
+    type Item = (Response<D::Item>, ExchangeTimings, BufferedIo<S>);
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let stream = self.stream.as_mut().expect("Cannot poll Exchange twice");
+        loop {
+            track!(stream.execute_io().map_err(Error::from))?;
+            if self.first_byte_time.is_none() && !stream.read_buf_ref().is_empty() {
+                self.first_byte_time = Some(Instant::now());
+            }
+            track!(self
+                .encoder
+                .encode_to_write_buf(stream.write_buf_mut())
+                .map_err(Error::from))?;
+            track!(self
+                .decoder
+                .decode_from_read_buf(stream.read_buf_mut())
+                .map_err(decode_error))?;
+            if self.decoder.is_idle() {
+                let response = track!(self.decoder.finish_decoding().map_err(decode_error))?;
+                let first_byte_time = self.first_byte_time.unwrap_or_else(Instant::now);
+                let timings = ExchangeTimings {
+                    ttfb: first_byte_time - self.start_time,
+                    transfer: first_byte_time.elapsed(),
+                };
+                let stream = self.stream.take().expect("Never fails");
+                return Ok(Async::Ready((response, timings, stream)));
+            }
+            if stream.is_eos() {
+                track_panic!(ErrorKind::ConnectionReset, "Unexpected EOS");
+            }
+            if stream.would_block() {
+                return Ok(Async::NotReady);
+            }
+        }
+    }
+}
+
+/// Converts an error of the response decoder, whose invalid input is a malformed response.
+fn decode_error(e: bytecodec::Error) -> Error {
+    if *e.kind() == bytecodec::ErrorKind::InvalidInput {
+        ErrorKind::MalformedResponse.takes_over(e).into()
+    } else {
+        Error::from(e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tls::TlsConnectorBuilder;
+    use fibers::{Executor, InPlaceExecutor, Spawn};
+    use std::collections::BTreeMap;
+    use std::net::TcpListener;
+    use std::thread;
+
+    const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/localhost.crt");
+    const KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/localhost.key");
+
+    /// Serves `n` requests over the first connection accepted by `listener`.
+    fn serve_tls(listener: TcpListener, n: usize) {
+        let identity = native_tls::Identity::from_pkcs8(
+            &std::fs::read(CERT).unwrap(),
+            &std::fs::read(KEY).unwrap(),
+        )
+        .unwrap();
+        let acceptor = native_tls::TlsAcceptor::new(identity).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut stream = acceptor.accept(stream).unwrap();
+        for _ in 0..n {
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let size = stream.read(&mut buf).unwrap();
+                assert_ne!(size, 0);
+                request.extend_from_slice(&buf[..size]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn pooled_tls_connection_works() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || serve_tls(listener, 2));
+
+        let tls = TlsConnectorBuilder::new()
+            .ca_cert_file(CERT)
+            .finish()
+            .unwrap();
+        let request = Request {
+            method: Method::Get,
+            url: format!("https://127.0.0.1:{}/", port).parse().unwrap(),
+            content: None,
+            timeout: None,
+            start_time: None,
+            headers: BTreeMap::new(),
+            expect: None,
+            name: None,
+            tags: Vec::new(),
+        };
+        let pool = ConnectionPool::new(1);
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let timeout = Some(Duration::from_secs(5));
+
+        let future = pool.send(&request, Some(&tls), timeout).unwrap();
+        let monitor = executor.handle().spawn_monitor(future);
+        let (response, phases) = executor.run_fiber(monitor).unwrap().unwrap();
+        assert_eq!(response.body(), b"ok");
+        assert!(phases.tls.is_some_and(|t| t.0 > 0.0));
+
+        // The server accepts only one connection.
+        let future = pool.send(&request, Some(&tls), timeout).unwrap();
+        let monitor = executor.handle().spawn_monitor(future);
+        let (response, phases) = executor.run_fiber(monitor).unwrap().unwrap();
+        assert_eq!(response.body(), b"ok");
+        assert_eq!(phases.tls, Some(Seconds(0.0)));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn closed_idle_connection_is_replaced() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (close_tx, close_rx) = std::sync::mpsc::channel();
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let size = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..size]);
+                }
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                    .unwrap();
+
+                // Closes the idle connection as if its keep-alive timeout has expired.
+                close_rx.recv().unwrap();
+            }
+        });
+
+        let request = Request {
+            method: Method::Get,
+            url: format!("http://localhost:{}/", port).parse().unwrap(),
+            content: None,
+            timeout: None,
+            start_time: None,
+            headers: BTreeMap::new(),
+            expect: None,
+            name: None,
+            tags: Vec::new(),
+        };
+        let pool = ConnectionPool::new(1);
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let timeout = Some(Duration::from_secs(5));
+        for _ in 0..2 {
+            let future = pool.send(&request, None, timeout).unwrap();
+            let monitor = executor.handle().spawn_monitor(future);
+            let (response, phases) = executor.run_fiber(monitor).unwrap().unwrap();
+            assert_eq!(response.body(), b"ok");
+            assert!(phases.connect.0 > 0.0);
+
+            close_tx.send(()).unwrap();
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(pool.inner.lock().unwrap().size, 1);
+        server.join().unwrap();
+    }
+
+    /// Reads a chunk of a request on the first connection, replies `response` and closes the connection.
+    fn serve_once(response: &'static [u8]) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.read(&mut [0; 1024]);
+            let _ = stream.write_all(response);
+        });
+        port
+    }
+
+    #[test]
+    fn error_kinds_are_classified() {
+        let refused_port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let cases = [
+            ("http://nonexistent.invalid/".to_owned(), ErrorKind::Dns),
+            (
+                format!("http://127.0.0.1:{}/", refused_port),
+                ErrorKind::ConnectionRefused,
+            ),
+            (
+                format!("http://127.0.0.1:{}/", serve_once(b"")),
+                ErrorKind::ConnectionReset,
+            ),
+            (
+                format!(
+                    "https://127.0.0.1:{}/",
+                    serve_once(b"HTTP/1.1 200 OK\r\n\r\n")
+                ),
+                ErrorKind::Tls,
+            ),
+            (
+                format!(
+                    "http://127.0.0.1:{}/",
+                    serve_once(b"HTTP/1.1 abc OK\r\n\r\n")
+                ),
+                ErrorKind::MalformedResponse,
+            ),
+        ];
+
+        let pool = ConnectionPool::new(cases.len());
+        let mut executor = InPlaceExecutor::new().unwrap();
+        for (url, kind) in cases {
+            let request = Request {
+                method: Method::Get,
+                url: url.parse().unwrap(),
+                content: None,
+                timeout: None,
+                start_time: None,
+                headers: BTreeMap::new(),
+                expect: None,
+                name: None,
+                tags: Vec::new(),
+            };
+            let future = pool
+                .send(&request, None, Some(Duration::from_secs(5)))
+                .unwrap();
+            let monitor = executor.handle().spawn_monitor(future);
+            let e = Error::from(executor.run_fiber(monitor).unwrap().unwrap_err());
+            assert_eq!(*e.kind(), kind, "{}: {}", url, e);
+        }
+
+        let e = Error::from(bytecodec::Error::from(bytecodec::ErrorKind::InvalidInput));
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn build_request_works() {
+        let mut request = Request {
+            method: Method::Post,
+            url: "http://localhost:8080/a?b=c".parse().unwrap(),
+            content: Some(crate::request::Content::Text("hi".to_owned())),
+            timeout: None,
+            start_time: None,
+            headers: BTreeMap::new(),
+            expect: None,
+            name: None,
+            tags: Vec::new(),
+        };
+        request
+            .headers
+            .insert("User-Agent".to_owned(), "hb (test)".to_owned());
+        let bytes = build_request(&request, false).unwrap();
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            "POST /a?b=c HTTP/1.1\r\nHost: localhost:8080\r\nUser-Agent: hb (test)\r\n\
+             Content-Length: 2\r\nConnection: close\r\n\r\nhi"
+        );
+
+        // `Request::headers` can be modified without `validate_header`.
+        request
+            .headers
+            .insert("X-Foo".to_owned(), "a\r\nX-Bar: b".to_owned());
+        let e = build_request(&request, true).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+    }
+}
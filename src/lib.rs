@@ -3,11 +3,25 @@ extern crate trackable;
 
 pub use error::{Error, ErrorKind};
 
+pub mod abort;
+pub mod compare;
+pub mod expect;
+pub mod flow;
+pub mod histogram;
+pub mod merge;
+pub mod mix;
+pub mod profile;
+pub mod progress;
+pub mod replay;
 pub mod request;
 pub mod run;
+pub mod saturation;
 pub mod summary;
+pub mod template;
 pub mod time_series;
+pub mod tls;
 
 mod error;
+mod http;
 
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -5,7 +5,12 @@ use clap::Parser;
 use fibers::{Executor, InPlaceExecutor, Spawn, ThreadPoolExecutor};
 use hb::Error;
 use std::fs::File;
-use std::io::{self, BufReader};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Mutex, Once};
+use std::time::Duration;
+use trackable::error::ErrorKindExt;
 
 #[derive(Parser)]
 #[clap(version)]
@@ -22,8 +27,16 @@ enum Command {
     Delete(DeleteCommand),
     Put(PutCommand),
     Post(PostCommand),
+    Patch(PatchCommand),
+    Options(OptionsCommand),
+    Custom(CustomCommand),
+    Import(ImportCommand),
     Summary(SummaryCommand),
+    Compare(CompareCommand),
+    FindMax(FindMaxCommand),
     TimeSeries(TimeSeriesCommand),
+    HdrSummary(HdrSummaryCommand),
+    Merge(MergeCommand),
 }
 
 fn main() {
@@ -37,34 +50,80 @@ fn main() {
         Command::Delete(c) => c.execute(),
         Command::Put(c) => c.execute(),
         Command::Post(c) => c.execute(),
+        Command::Patch(c) => c.execute(),
+        Command::Options(c) => c.execute(),
+        Command::Custom(c) => c.execute(),
+        Command::Import(c) => c.execute(),
         Command::Summary(c) => c.execute(),
+        Command::Compare(c) => c.execute(),
+        Command::FindMax(c) => c.execute(),
         Command::TimeSeries(c) => c.execute(),
+        Command::HdrSummary(c) => c.execute(),
+        Command::Merge(c) => c.execute(),
     }
 }
 
 fn execute_runner<E: Executor>(
     mut executor: E,
-    concurrency: usize,
-    connection_pool_size: usize,
+    options: &RunnerOptions,
+    builder: &hb::run::RunnerBuilder,
     requests: &hb::run::RequestQueue,
-) -> hb::Result<Vec<hb::run::RequestResult>> {
-    let runner = hb::run::RunnerBuilder::new()
-        .concurrency(concurrency)
-        .connection_pool_size(connection_pool_size)
-        .finish(&executor.handle(), requests);
+    ndjson_writer: Option<Box<dyn Write + Send>>,
+) -> hb::Result<hb::run::RunOutput> {
+    let mut runner = builder.finish(&executor.handle(), requests);
+    if let Some(writer) = ndjson_writer {
+        track!(runner.set_ndjson_writer(writer))?;
+    }
+    if !options.quiet {
+        let format = match options.progress_format {
+            ProgressFormat::Text => hb::progress::ProgressFormat::Text,
+            ProgressFormat::Json => hb::progress::ProgressFormat::Json,
+        };
+        runner.set_progress_writer(io::stderr(), format, options.progress_interval);
+    }
+    if let Some(path) = &options.hdr_log {
+        let f = track!(File::create(path).map_err(Error::from))?;
+        track!(runner.set_histogram_log_writer(f, options.hdr_log_interval))?;
+    }
+    track!(set_stop_handle(runner.stop_handle()))?;
     let monitor = executor.handle().spawn_monitor(runner);
     let result = track!(executor.run_fiber(monitor).map_err(Error::from))?;
     track!(result.map_err(Error::from))
 }
 
-#[derive(clap::Args)]
-struct RunCommand {
-    #[clap(short, long, default_value = "-")]
-    input: String,
+static STOP_HANDLE: Mutex<Option<hb::run::StopHandle>> = Mutex::new(None);
 
-    #[clap(short, long, default_value = "-")]
-    output: String,
+/// Makes SIGINT/SIGTERM stop the current run gracefully (a second signal exits immediately).
+fn set_stop_handle(handle: hb::run::StopHandle) -> hb::Result<()> {
+    static INIT: Once = Once::new();
+    let mut result = Ok(());
+    INIT.call_once(|| {
+        result = ctrlc::set_handler(|| {
+            let handle = STOP_HANDLE.lock().expect("Never fails");
+            match handle.as_ref() {
+                Some(handle) if !handle.is_stopped() => {
+                    eprintln!(
+                        "Interrupted: waiting for in-flight requests (press Ctrl-C again to abort)"
+                    );
+                    handle.stop();
+                }
+                _ => std::process::exit(EXIT_INTERRUPTED),
+            }
+        })
+        .map_err(|e| hb::ErrorKind::Other.cause(e).into());
+    });
+    *STOP_HANDLE.lock().expect("Never fails") = Some(handle);
+    track!(result)
+}
 
+/// The conventional exit code of a process terminated by SIGINT.
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// The exit code used when a run is aborted by one of the `--abort-on-*` conditions or `--max-wall-time`.
+const EXIT_ABORTED: i32 = 3;
+
+#[derive(clap::Args)]
+struct RunnerOptions {
     #[clap(short, long, default_value_t = 32)]
     concurrency: usize,
 
@@ -73,122 +132,586 @@ struct RunCommand {
 
     #[clap(short, long, default_value_t = 2)]
     threads: usize,
+
+    /// Stops issuing new requests once the given duration (e.g., "60s", "2h") has elapsed
+    #[clap(long, value_parser = parse_duration)]
+    duration: Option<Duration>,
+
+    /// Sends each request over a new connection (so that the DNS, connect and TLS phases of every request are measured)
+    #[clap(long)]
+    no_keepalive: bool,
+
+    /// How long to wait for in-flight requests after the run is interrupted
+    #[clap(long, value_parser = parse_duration, default_value = "5s")]
+    shutdown_timeout: Duration,
+
+    /// Load profile stages such as "0s:10,2m:500,10m:500,0s:10" (DURATION:TARGET[:NAME], each ramping linearly from the previous target)
+    #[clap(long, conflicts_with = "profile")]
+    stages: Option<String>,
+
+    /// What the targets of `--stages` control
+    #[clap(long, value_enum, default_value_t = ProfileKind::Concurrency)]
+    stage_target: ProfileKind,
+
+    /// JSON file of a load profile (see `hb::profile::Profile`)
+    #[clap(long, value_parser = parse_profile)]
+    profile: Option<hb::profile::Profile>,
+
+    /// Aborts the run if the error rate (in percent) of the latest `--abort-window` results exceeds this
+    #[clap(long)]
+    abort_on_error_rate: Option<f64>,
+
+    /// Aborts the run if this number of requests fail in a row
+    #[clap(
+        long,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    abort_on_consecutive_failures: Option<usize>,
+
+    /// Aborts the run if the latency at `--abort-latency-percentile` of the latest `--abort-window` results exceeds this (e.g., "500ms")
+    #[clap(long, value_parser = parse_duration)]
+    abort_on_latency: Option<Duration>,
+
+    #[clap(long, value_parser = parse_percentile, default_value_t = 99.0)]
+    abort_latency_percentile: f64,
+
+    /// Number of the latest results checked by `--abort-on-error-rate` and `--abort-on-latency`
+    #[clap(
+        long,
+        default_value_t = 100,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    abort_window: usize,
+
+    /// Aborts the run (without waiting for in-flight requests) once this duration has elapsed
+    #[clap(long, value_parser = parse_duration)]
+    max_wall_time: Option<Duration>,
+
+    /// Suppresses the progress reports written to stderr during the run
+    #[clap(short, long)]
+    quiet: bool,
+
+    #[clap(long, value_enum, default_value_t = ProgressFormat::Text)]
+    progress_format: ProgressFormat,
+
+    #[clap(long, value_parser = parse_interval, default_value = "1s")]
+    progress_interval: Duration,
+
+    /// Writes the latencies as an HdrHistogram interval log (in microseconds) to the given file
+    #[clap(long)]
+    hdr_log: Option<PathBuf>,
+
+    /// Length of each interval of the `--hdr-log` (e.g., "10s")
+    #[clap(long, value_parser = parse_interval, default_value = "1s", requires = "hdr_log")]
+    hdr_log_interval: Duration,
+
+    #[clap(flatten)]
+    tls: TlsOptions,
 }
 
-impl RunCommand {
-    fn execute(&self) {
-        let requests = match self.input.as_str() {
-            "-" => {
-                let stdin = io::stdin();
-                track_try_unwrap!(hb::run::RequestQueue::read_from(stdin.lock()))
-            }
-            filepath => {
-                let f = track_try_unwrap!(File::open(filepath).map_err(Error::from));
-                track_try_unwrap!(hb::run::RequestQueue::read_from(BufReader::new(f)))
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ProgressFormat {
+    Text,
+
+    /// A JSON object per line
+    Json,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ProfileKind {
+    /// Number of concurrent clients (overrides `--concurrency`)
+    Concurrency,
+
+    /// Requests per second
+    Rate,
+}
+
+#[derive(clap::Args)]
+struct TlsOptions {
+    /// PEM file of CA certificates to trust in addition to the system ones
+    #[clap(long)]
+    cacert: Vec<PathBuf>,
+
+    /// Skips the verification of server certificates
+    #[clap(short = 'k', long)]
+    insecure: bool,
+
+    /// PEM file of the client certificate for mutual TLS
+    #[clap(long, requires = "key")]
+    cert: Option<PathBuf>,
+
+    /// PEM file of the (PKCS#8) private key of the client certificate
+    #[clap(long, requires = "cert")]
+    key: Option<PathBuf>,
+}
+
+impl TlsOptions {
+    /// Returns `None` if no options are specified (i.e., the default connector is enough).
+    fn to_connector(&self) -> hb::Result<Option<hb::tls::TlsConnector>> {
+        if self.cacert.is_empty() && !self.insecure && self.cert.is_none() {
+            return Ok(None);
+        }
+        let mut builder = hb::tls::TlsConnectorBuilder::new();
+        for path in &self.cacert {
+            builder.ca_cert_file(path);
+        }
+        builder.insecure(self.insecure);
+        if let (Some(cert), Some(key)) = (&self.cert, &self.key) {
+            builder.client_identity(cert, key);
+        }
+        track!(builder.finish()).map(Some)
+    }
+}
+
+impl RunnerOptions {
+    fn profile(&self) -> hb::Result<Option<hb::profile::Profile>> {
+        if let Some(profile) = &self.profile {
+            Ok(Some(profile.clone()))
+        } else if let Some(stages) = &self.stages {
+            let kind = match self.stage_target {
+                ProfileKind::Concurrency => hb::profile::ProfileKind::Concurrency,
+                ProfileKind::Rate => hb::profile::ProfileKind::Rate,
+            };
+            let profile = track!(hb::profile::Profile::parse_stages(kind, stages))?;
+            Ok(Some(profile))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns the rate profile (if any).
+    fn rate_profile(&self) -> Option<hb::profile::Profile> {
+        track_try_unwrap!(self.profile()).filter(|p| p.kind == hb::profile::ProfileKind::Rate)
+    }
+
+    /// Makes a queue of the given requests, scheduling them by the rate profile (if any).
+    fn queue<I>(
+        &self,
+        requests: I,
+        rate_profile: Option<hb::profile::Profile>,
+    ) -> hb::run::RequestQueue
+    where
+        I: Iterator<Item = hb::Result<hb::request::Request>> + Send + 'static,
+    {
+        match rate_profile {
+            Some(profile) => {
+                let requests = requests.zip(profile.start_times()).map(|(r, t)| {
+                    r.map(|mut r| {
+                        r.start_time = Some(t);
+                        r
+                    })
+                });
+                hb::run::RequestQueue::with_fallible_generator(requests)
             }
-        };
+            None => hb::run::RequestQueue::with_fallible_generator(requests),
+        }
+    }
+
+    /// Returns `true` if the run is bounded by time rather than by the number of requests.
+    fn is_time_bounded(&self) -> bool {
+        self.duration.is_some() || self.stages.is_some() || self.profile.is_some()
+    }
+
+    /// Makes a runner builder configured by these options.
+    fn runner_builder(&self) -> hb::Result<hb::run::RunnerBuilder> {
+        let mut builder = hb::run::RunnerBuilder::new();
+        builder
+            .concurrency(self.concurrency)
+            .connection_pool_size(self.connection_pool_size)
+            .shutdown_timeout(self.shutdown_timeout);
+        if let Some(duration) = self.duration {
+            builder.duration(duration);
+        }
+        if let Some(profile) = track!(self.profile())? {
+            builder.profile(profile);
+        }
+        builder.abort_conditions(hb::abort::AbortConditions {
+            max_error_rate: self.abort_on_error_rate,
+            max_consecutive_failures: self.abort_on_consecutive_failures,
+            max_latency: self.abort_on_latency.map(hb::run::Seconds::from),
+            latency_percentile: self.abort_latency_percentile,
+            window: self.abort_window,
+            max_wall_time: self.max_wall_time,
+        });
+        builder.keep_alive(!self.no_keepalive);
+        if let Some(connector) = track!(self.tls.to_connector())? {
+            builder.tls_connector(connector);
+        }
+        Ok(builder)
+    }
+
+    fn execute(
+        &self,
+        requests: &hb::run::RequestQueue,
+        ndjson_writer: Option<Box<dyn Write + Send>>,
+    ) -> hb::run::RunOutput {
+        let builder = track_try_unwrap!(self.runner_builder());
+        track_try_unwrap!(self.execute_with(&builder, requests, ndjson_writer))
+    }
 
-        let responses = if self.threads == 1 {
-            let executor = track_try_unwrap!(InPlaceExecutor::new().map_err(Error::from));
-            track_try_unwrap!(execute_runner(
+    fn execute_with(
+        &self,
+        builder: &hb::run::RunnerBuilder,
+        requests: &hb::run::RequestQueue,
+        ndjson_writer: Option<Box<dyn Write + Send>>,
+    ) -> hb::Result<hb::run::RunOutput> {
+        if self.threads == 1 {
+            let executor = track!(InPlaceExecutor::new().map_err(Error::from))?;
+            track!(execute_runner(
                 executor,
-                self.concurrency,
-                self.connection_pool_size,
-                &requests
+                self,
+                builder,
+                requests,
+                ndjson_writer
             ))
         } else {
-            let executor = track_try_unwrap!(
-                ThreadPoolExecutor::with_thread_count(self.threads).map_err(Error::from)
-            );
-            track_try_unwrap!(execute_runner(
+            let executor =
+                track!(ThreadPoolExecutor::with_thread_count(self.threads).map_err(Error::from))?;
+            track!(execute_runner(
                 executor,
-                self.concurrency,
-                self.connection_pool_size,
-                &requests
+                self,
+                builder,
+                requests,
+                ndjson_writer
             ))
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ResultFormat {
+    /// A pretty-printed JSON object with the format version, written after the run finishes
+    /// (a plain JSON array of results written by older versions is also readable)
+    Json,
+
+    /// One JSON object per line, written as each result arrives
+    Ndjson,
+}
+
+impl ResultFormat {
+    fn to_hb(self) -> hb::run::ResultFormat {
+        match self {
+            ResultFormat::Json => hb::run::ResultFormat::Json,
+            ResultFormat::Ndjson => hb::run::ResultFormat::Ndjson,
+        }
+    }
+}
+
+/// Reads a result file ("-" for stdin).
+fn read_records(input: &str, format: ResultFormat) -> hb::Result<Vec<hb::run::Record>> {
+    match input {
+        "-" => {
+            let stdin = io::stdin();
+            track!(hb::run::read_records(stdin.lock(), format.to_hb()))
+        }
+        filepath => {
+            let f = track!(File::open(filepath).map_err(Error::from); filepath)?;
+            track!(hb::run::read_records(BufReader::new(f), format.to_hb()); filepath)
+        }
+    }
+}
+
+/// Reads the results of a result file ("-" for stdin).
+fn read_results(input: &str, format: ResultFormat) -> hb::Result<Vec<hb::run::RequestResult>> {
+    match input {
+        "-" => {
+            let stdin = io::stdin();
+            track!(hb::run::read_results(stdin.lock(), format.to_hb()))
+        }
+        filepath => {
+            let f = track!(File::open(filepath).map_err(Error::from); filepath)?;
+            track!(hb::run::read_results(BufReader::new(f), format.to_hb()); filepath)
+        }
+    }
+}
+
+#[derive(clap::Args)]
+struct ResultOutputOptions {
+    #[clap(short, long, default_value = "-")]
+    output: String,
+
+    #[clap(long, value_enum, default_value_t = ResultFormat::Json)]
+    format: ResultFormat,
+}
+
+impl ResultOutputOptions {
+    fn run(&self, runner: &RunnerOptions, requests: &hb::run::RequestQueue) {
+        if self.format == ResultFormat::Ndjson {
+            let writer: Box<dyn Write + Send> = match self.output.as_str() {
+                "-" => Box::new(io::stdout()),
+                filepath => Box::new(track_try_unwrap!(
+                    File::create(filepath).map_err(Error::from)
+                )),
+            };
+            let output = runner.execute(requests, Some(writer));
+            exit_if_stopped(&output);
+            return;
+        }
+
+        let output = runner.execute(requests, None);
+        let mut records = vec![hb::run::Record::Started(output.started.clone())];
+        records.extend(output.results.iter().cloned().map(hb::run::Record::Result));
+        records.extend(output.stopped.clone().map(hb::run::Record::Stopped));
+        track_try_unwrap!(self.write_records(&records));
+        exit_if_stopped(&output);
+    }
+
+    fn write_records(&self, records: &[hb::run::Record]) -> hb::Result<()> {
+        let mut writer: Box<dyn Write> = match self.output.as_str() {
+            "-" => Box::new(io::stdout()),
+            filepath => Box::new(BufWriter::new(track!(
+                File::create(filepath).map_err(Error::from)
+            )?)),
         };
+        track!(hb::run::write_records(
+            &mut writer,
+            records,
+            self.format.to_hb()
+        ))?;
+        if self.output == "-" && self.format == ResultFormat::Json {
+            track!(writeln!(writer).map_err(Error::from))?;
+        }
+        track!(writer.flush().map_err(Error::from))?;
+        Ok(())
+    }
+}
 
-        match self.output.as_str() {
-            "-" => {
-                track_try_unwrap!(serdeconv::to_json_writer_pretty(&responses, io::stdout()));
-                println!();
-            }
-            filepath => {
-                let f = track_try_unwrap!(File::create(filepath).map_err(Error::from));
-                track_try_unwrap!(serdeconv::to_json_writer_pretty(&responses, f));
-            }
+fn exit_if_stopped(output: &hb::run::RunOutput) {
+    match output.stopped.as_ref().map(|s| &s.reason) {
+        None => {}
+        Some(hb::run::StopReason::Interrupted) => std::process::exit(EXIT_INTERRUPTED),
+        Some(reason) => {
+            eprintln!("Aborted: {}", reason);
+            std::process::exit(EXIT_ABORTED);
         }
     }
 }
 
+#[derive(clap::Args)]
+#[clap(group(clap::ArgGroup::new("generated").args(["template", "flow", "mix"])))]
+#[clap(group(clap::ArgGroup::new("seeded").args(["template", "mix"])))]
+struct RunCommand {
+    #[clap(short, long, default_value = "-")]
+    input: String,
+
+    /// Treats the input as a request template file (see `hb::template::TemplateFile`)
+    #[clap(long)]
+    template: bool,
+
+    /// Treats the input as a flow file (see `hb::flow::FlowFile`)
+    #[clap(long)]
+    flow: bool,
+
+    /// Treats the input as a weighted mix of requests (see `hb::mix::MixFile`)
+    #[clap(long)]
+    mix: bool,
+
+    /// Seed of the random template variables (or the sampling of the mix)
+    #[clap(long, requires = "seeded")]
+    seed: Option<u64>,
+
+    /// Number of requests generated from the templates or the mix (or number of flow executions)
+    #[clap(short = 'n', long, requires = "generated")]
+    requests: Option<usize>,
+
+    #[clap(flatten)]
+    output: ResultOutputOptions,
+
+    #[clap(flatten)]
+    runner: RunnerOptions,
+}
+
+impl RunCommand {
+    fn execute(&self) {
+        let input: Box<dyn io::Read> = match self.input.as_str() {
+            "-" => Box::new(io::stdin()),
+            filepath => Box::new(BufReader::new(track_try_unwrap!(
+                File::open(filepath).map_err(Error::from)
+            ))),
+        };
+        let rate_profile = self.runner.rate_profile();
+        let requests = if self.template {
+            let file = track_try_unwrap!(hb::template::TemplateFile::read_from(input));
+            let generator = track_try_unwrap!(hb::template::RequestGenerator::new(
+                file,
+                self.seed,
+                self.requests,
+                self.runner.is_time_bounded()
+            ));
+            log::info!("Template seed: {}", generator.seed());
+            self.runner.queue(generator, rate_profile)
+        } else if self.mix {
+            let file = track_try_unwrap!(hb::mix::MixFile::read_from(input));
+            let generator = track_try_unwrap!(hb::mix::MixGenerator::new(
+                file,
+                self.seed,
+                self.requests,
+                self.runner.is_time_bounded()
+            ));
+            log::info!("Mix seed: {}", generator.seed());
+            self.runner.queue(generator.map(Ok), rate_profile)
+        } else if self.flow {
+            if rate_profile.is_some() {
+                eprintln!("Flows cannot be run with rate profiles");
+                std::process::exit(1);
+            }
+            let file = track_try_unwrap!(hb::flow::FlowFile::read_from(input));
+            let sessions = track_try_unwrap!(hb::flow::FlowGenerator::new(
+                file,
+                self.requests,
+                self.runner.is_time_bounded()
+            ));
+            hb::run::RequestQueue::with_flows(sessions)
+        } else if rate_profile.is_some() {
+            let requests: Vec<hb::request::Request> =
+                track_try_unwrap!(serdeconv::from_json_reader(input));
+            self.runner
+                .queue(requests.into_iter().map(Ok), rate_profile)
+        } else {
+            track_try_unwrap!(hb::run::RequestQueue::read_from(input))
+        };
+
+        self.output.run(&self.runner, &requests);
+    }
+}
+
 #[derive(clap::Args)]
 struct RequestCommand {
     urls: Vec<url::Url>,
 
-    #[clap(short = 'n', long, default_value_t = 10)]
-    requests: usize,
+    /// Number of requests [default: 10, or unlimited if `--duration` or a load profile is specified]
+    #[clap(short = 'n', long)]
+    requests: Option<usize>,
 
-    #[clap(short, long, default_value = "-")]
-    output: String,
+    #[clap(flatten)]
+    output: ResultOutputOptions,
 
-    #[clap(short, long, default_value_t = 32)]
-    concurrency: usize,
+    #[clap(flatten)]
+    runner: RunnerOptions,
 
-    #[clap(long, default_value_t = 4096)]
-    connection_pool_size: usize,
+    /// Adds a header to each request (e.g., "Authorization: Bearer xxx")
+    #[clap(short = 'H', long = "header", value_parser = parse_header)]
+    headers: Vec<(String, String)>,
 
-    #[clap(short, long, default_value_t = 2)]
-    threads: usize,
+    /// Issues requests at a constant rate (e.g., "5000/s", "300/m") instead of as fast as possible
+    ///
+    /// Note that the number of in-flight requests is still bounded by `--concurrency`.
+    #[clap(long, value_parser = parse_rate, conflicts_with_all = ["stages", "profile"])]
+    rate: Option<f64>,
 }
 
 impl RequestCommand {
     fn execute(&self, method: hb::request::Method, content: Option<&hb::request::Content>) {
-        let requests = self
-            .urls
-            .iter()
-            .cycle()
-            .zip(0..self.requests)
-            .map(|(url, _)| hb::request::Request {
-                method,
-                url: url.clone(),
-                content: content.cloned(),
-                timeout: None,
-                start_time: None,
-            })
-            .collect();
-        let requests = hb::run::RequestQueue::new(requests);
-        let responses = if self.threads == 1 {
-            let executor = track_try_unwrap!(InPlaceExecutor::new().map_err(Error::from));
-            track_try_unwrap!(execute_runner(
-                executor,
-                self.concurrency,
-                self.connection_pool_size,
-                &requests
-            ))
-        } else {
-            let executor = track_try_unwrap!(
-                ThreadPoolExecutor::with_thread_count(self.threads).map_err(Error::from)
-            );
-            track_try_unwrap!(execute_runner(
-                executor,
-                self.concurrency,
-                self.connection_pool_size,
-                &requests
-            ))
+        let limit = match self.requests {
+            Some(n) => n,
+            None if self.runner.is_time_bounded() => usize::MAX,
+            None => hb::run::DEFAULT_REQUEST_COUNT,
         };
-        match self.output.as_str() {
-            "-" => {
-                track_try_unwrap!(serdeconv::to_json_writer_pretty(&responses, io::stdout()));
-                println!();
-            }
-            filepath => {
-                let f = track_try_unwrap!(File::create(filepath).map_err(Error::from));
-                track_try_unwrap!(serdeconv::to_json_writer_pretty(&responses, f));
-            }
-        }
+        let urls = self.urls.clone();
+        let content = content.cloned();
+        let headers = track_try_unwrap!(hb::request::collect_headers(self.headers.clone()));
+        let rate = self.rate;
+        let requests = (0..limit)
+            .zip(urls.into_iter().cycle())
+            .map(move |(i, url)| hb::request::Request {
+                method: method.clone(),
+                url,
+                content: content.clone(),
+                timeout: None,
+                start_time: rate.map(|rate| hb::run::Seconds(i as f64 / rate)),
+                headers: headers.clone(),
+                expect: None,
+                name: None,
+                tags: Vec::new(),
+            });
+        let requests = self
+            .runner
+            .queue(requests.map(Ok), self.runner.rate_profile());
+        self.output.run(&self.runner, &requests);
     }
 }
 
+fn parse_header(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once(':')
+        .ok_or_else(|| format!("header must be of the form \"NAME: VALUE\": {:?}", s))?;
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(format!("empty header name: {:?}", s));
+    }
+
+    let value = value.trim();
+    if hb::request::validate_header(name, value).is_err() {
+        return Err(format!(
+            "header name must be a token and its value must not contain control characters: {:?}",
+            s
+        ));
+    }
+    Ok((name.to_owned(), value.to_owned()))
+}
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let seconds = hb::run::Seconds::from_str(s).map_err(|_| {
+        format!(
+            "invalid duration {:?} (expected a number followed by ms, s, m or h)",
+            s
+        )
+    })?;
+    Ok(seconds.into())
+}
+
+fn parse_profile(s: &str) -> Result<hb::profile::Profile, String> {
+    let f = File::open(s).map_err(|e| format!("cannot open {:?}: {}", s, e))?;
+    hb::profile::Profile::read_from(BufReader::new(f))
+        .map_err(|e| format!("invalid profile {:?}: {}", s, e))
+}
+
+/// Parses the interval of a periodic task, which must not be zero.
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    let interval = parse_duration(s)?;
+    if interval.is_zero() {
+        return Err(format!("interval must be positive: {:?}", s));
+    }
+    Ok(interval)
+}
+
+fn parse_rate(s: &str) -> Result<f64, String> {
+    let (count, unit) = s.split_once('/').unwrap_or((s, "s"));
+    let count: f64 = count
+        .parse()
+        .map_err(|e| format!("invalid rate {:?}: {}", s, e))?;
+    let seconds = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 60.0 * 60.0,
+        _ => return Err(format!("unknown rate unit {:?} (expected s, m or h)", unit)),
+    };
+    if !(count > 0.0 && count.is_finite()) {
+        return Err(format!("rate must be a positive number: {:?}", s));
+    }
+    Ok(count / seconds)
+}
+
+fn parse_speed(s: &str) -> Result<f64, String> {
+    let speed: f64 = s
+        .parse()
+        .map_err(|e| format!("invalid speed {:?}: {}", s, e))?;
+    if !(speed > 0.0 && speed.is_finite()) {
+        return Err(format!("speed must be a positive number: {:?}", s));
+    }
+    Ok(speed)
+}
+
+fn parse_percentile(s: &str) -> Result<f64, String> {
+    let p: f64 = s
+        .parse()
+        .map_err(|e| format!("invalid percentile {:?}: {}", s, e))?;
+    if !(0.0..=100.0).contains(&p) {
+        return Err(format!("percentile must be between 0 and 100: {:?}", s));
+    }
+    Ok(p)
+}
+
 #[derive(clap::Args)]
 struct GetCommand {
     #[clap(flatten)]
@@ -238,10 +761,7 @@ impl PutCommand {
 }
 
 #[derive(clap::Args)]
-struct PostCommand {
-    #[clap(flatten)]
-    request: RequestCommand,
-
+struct ContentArgs {
     #[clap(long)]
     content_length: Option<usize>,
 
@@ -249,17 +769,148 @@ struct PostCommand {
     content: Option<String>,
 }
 
+impl ContentArgs {
+    fn to_content(&self) -> Option<hb::request::Content> {
+        if let Some(text) = &self.content {
+            Some(hb::request::Content::Text(text.to_owned()))
+        } else {
+            self.content_length.map(hb::request::Content::Size)
+        }
+    }
+}
+
+#[derive(clap::Args)]
+struct PostCommand {
+    #[clap(flatten)]
+    request: RequestCommand,
+
+    #[clap(flatten)]
+    content: ContentArgs,
+}
+
 impl PostCommand {
     fn execute(&self) {
-        let content = if let Some(text) = &self.content {
-            Some(hb::request::Content::Text(text.to_owned()))
-        } else if let Some(len) = self.content_length {
-            Some(hb::request::Content::Size(len))
+        self.request.execute(
+            hb::request::Method::Post,
+            self.content.to_content().as_ref(),
+        )
+    }
+}
+
+#[derive(clap::Args)]
+struct PatchCommand {
+    #[clap(flatten)]
+    request: RequestCommand,
+
+    #[clap(flatten)]
+    content: ContentArgs,
+}
+
+impl PatchCommand {
+    fn execute(&self) {
+        self.request.execute(
+            hb::request::Method::Patch,
+            self.content.to_content().as_ref(),
+        )
+    }
+}
+
+#[derive(clap::Args)]
+struct OptionsCommand {
+    #[clap(flatten)]
+    request: RequestCommand,
+}
+
+impl OptionsCommand {
+    fn execute(&self) {
+        self.request.execute(hb::request::Method::Options, None);
+    }
+}
+
+#[derive(clap::Args)]
+struct CustomCommand {
+    #[clap(flatten)]
+    request: RequestCommand,
+
+    /// HTTP method name (e.g., "PURGE")
+    #[clap(short = 'X', long, value_parser = hb::request::Method::from_str)]
+    method: hb::request::Method,
+
+    #[clap(flatten)]
+    content: ContentArgs,
+}
+
+impl CustomCommand {
+    fn execute(&self) {
+        self.request
+            .execute(self.method.clone(), self.content.to_content().as_ref())
+    }
+}
+
+/// Converts recorded traffic into the request JSON accepted by `hb run`
+#[derive(clap::Args)]
+struct ImportCommand {
+    #[clap(short, long, default_value = "-")]
+    input: String,
+
+    #[clap(short, long, default_value = "-")]
+    output: String,
+
+    #[clap(long, value_enum)]
+    format: ImportFormat,
+
+    /// URL against which the paths in access logs are resolved (e.g., "http://localhost:8080")
+    #[clap(long, required_if_eq("format", "access-log"))]
+    base_url: Option<url::Url>,
+
+    /// Keeps the original inter-arrival times as the start times of the requests
+    #[clap(long)]
+    keep_timing: bool,
+
+    /// Speed-up factor applied to the original inter-arrival times
+    #[clap(long, default_value_t = 1.0, value_parser = parse_speed, requires = "keep_timing")]
+    speed: f64,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ImportFormat {
+    /// HTTP Archive
+    Har,
+
+    /// Common (or Combined) Log Format
+    AccessLog,
+}
+
+impl ImportCommand {
+    fn execute(&self) {
+        let input: Box<dyn io::BufRead> = match self.input.as_str() {
+            "-" => Box::new(BufReader::new(io::stdin())),
+            filepath => Box::new(BufReader::new(track_try_unwrap!(
+                File::open(filepath).map_err(Error::from)
+            ))),
+        };
+        let speed = if self.keep_timing {
+            Some(self.speed)
         } else {
             None
         };
-        self.request
-            .execute(hb::request::Method::Post, content.as_ref())
+        let requests = match self.format {
+            ImportFormat::Har => track_try_unwrap!(hb::replay::read_har(input, speed)),
+            ImportFormat::AccessLog => {
+                let base_url = self.base_url.as_ref().expect("Never fails");
+                track_try_unwrap!(hb::replay::read_access_log(input, base_url, speed))
+            }
+        };
+        match self.output.as_str() {
+            "-" => {
+                track_try_unwrap!(serdeconv::to_json_writer_pretty(&requests, io::stdout()));
+                println!();
+            }
+            filepath => {
+                let f = track_try_unwrap!(File::create(filepath).map_err(Error::from));
+                track_try_unwrap!(serdeconv::to_json_writer_pretty(&requests, f));
+            }
+        }
     }
 }
 
@@ -268,31 +919,373 @@ struct SummaryCommand {
     #[clap(short, long, default_value = "-")]
     input: String,
 
+    /// Format of the input result files
+    #[clap(long, value_enum, default_value_t = ResultFormat::Json)]
+    input_format: ResultFormat,
+
     #[clap(short, long, default_value = "-")]
     output: String,
+
+    /// Latency percentiles to report
+    #[clap(
+        long,
+        value_delimiter = ',',
+        value_parser = parse_percentile,
+        default_value = "50,75,90,95,99,99.9,99.99"
+    )]
+    percentiles: Vec<f64>,
+
+    /// Outputs a summary per group instead of the whole summary
+    #[clap(long, value_enum)]
+    group_by: Option<GroupBy>,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum GroupBy {
+    Url,
+    Method,
+    Status,
+
+    /// Each tag of the requests (see `hb::request::Request::tags`)
+    Tag,
+
+    /// The name of the requests (see `hb::request::Request::name`)
+    Name,
+
+    /// The result file from which the results were merged by `hb merge`
+    Source,
 }
 
 impl SummaryCommand {
     fn execute(&self) {
-        let responses = match self.input.as_str() {
+        let responses = track_try_unwrap!(read_results(&self.input, self.input_format));
+        let mut builder = hb::summary::SummaryBuilder::new();
+        builder.percentiles(self.percentiles.clone());
+        if let Some(group_by) = self.group_by {
+            let group_by = match group_by {
+                GroupBy::Url => hb::summary::GroupBy::Url,
+                GroupBy::Method => hb::summary::GroupBy::Method,
+                GroupBy::Status => hb::summary::GroupBy::Status,
+                GroupBy::Tag => hb::summary::GroupBy::Tag,
+                GroupBy::Name => hb::summary::GroupBy::Name,
+                GroupBy::Source => hb::summary::GroupBy::Source,
+            };
+            let summaries = builder.finish_grouped(responses, group_by);
+            self.write(&summaries);
+        } else {
+            self.write(&builder.finish(responses));
+        }
+    }
+
+    fn write<T: serde::Serialize>(&self, value: &T) {
+        match self.output.as_str() {
+            "-" => {
+                track_try_unwrap!(serdeconv::to_json_writer_pretty(value, io::stdout()));
+                println!();
+            }
+            filepath => {
+                let f = track_try_unwrap!(File::create(filepath).map_err(Error::from));
+                track_try_unwrap!(serdeconv::to_json_writer_pretty(value, f));
+            }
+        }
+    }
+}
+
+/// Compares the results of a candidate run against a baseline run
+///
+/// Exits with 1 if any of the given regression thresholds is exceeded.
+#[derive(clap::Args)]
+struct CompareCommand {
+    /// Result file of the baseline run
+    baseline: PathBuf,
+
+    /// Result file of the candidate run
+    candidate: PathBuf,
+
+    /// Format of the input result files
+    #[clap(long, value_enum, default_value_t = ResultFormat::Json)]
+    input_format: ResultFormat,
+
+    #[clap(short, long, default_value = "-")]
+    output: String,
+
+    /// Latency percentiles to compare
+    #[clap(
+        long,
+        value_delimiter = ',',
+        value_parser = parse_percentile,
+        default_value = "50,75,90,95,99,99.9,99.99"
+    )]
+    percentiles: Vec<f64>,
+
+    /// Maximum allowed decrease of the throughput in percent
+    #[clap(long)]
+    max_rps_decrease: Option<f64>,
+
+    /// Maximum allowed increase of the error rate in percentage points
+    #[clap(long)]
+    max_error_rate_increase: Option<f64>,
+
+    /// Maximum allowed increase of each latency percentile in percent (only checked if the latency difference is significant)
+    #[clap(long)]
+    max_latency_increase: Option<f64>,
+
+    /// Significance level of the Mann-Whitney U test on the latencies of the successful requests
+    #[clap(long, default_value_t = 0.05)]
+    alpha: f64,
+}
+
+impl CompareCommand {
+    fn execute(&self) {
+        let read = |path: &PathBuf| {
+            let path = path.to_string_lossy();
+            track_try_unwrap!(read_results(&path, self.input_format))
+        };
+        let thresholds = hb::compare::Thresholds {
+            max_rps_decrease: self.max_rps_decrease,
+            max_error_rate_increase: self.max_error_rate_increase,
+            max_latency_increase: self.max_latency_increase,
+            alpha: self.alpha,
+        };
+        let comparison = track_try_unwrap!(hb::compare::ComparisonBuilder::new()
+            .percentiles(self.percentiles.clone())
+            .thresholds(thresholds)
+            .finish(read(&self.baseline), read(&self.candidate)));
+        match self.output.as_str() {
             "-" => {
-                let stdin = io::stdin();
-                track_try_unwrap!(serdeconv::from_json_reader(stdin.lock()))
+                track_try_unwrap!(serdeconv::to_json_writer_pretty(&comparison, io::stdout()));
+                println!();
             }
             filepath => {
-                let f = track_try_unwrap!(File::open(filepath).map_err(Error::from));
-                track_try_unwrap!(serdeconv::from_json_reader(BufReader::new(f)))
+                let f = track_try_unwrap!(File::create(filepath).map_err(Error::from));
+                track_try_unwrap!(serdeconv::to_json_writer_pretty(&comparison, f));
             }
+        }
+        if comparison.has_regressions() {
+            for regression in &comparison.regressions {
+                eprintln!("Regression: {}", regression);
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Searches for the maximum load (concurrency or rate) that satisfies the latency and error rate limits
+#[derive(clap::Args)]
+struct FindMaxCommand {
+    /// URLs to request with GET (cycled)
+    #[clap(required_unless_present = "input")]
+    urls: Vec<url::Url>,
+
+    /// JSON file of requests to issue (cycled) instead of `URLS`
+    #[clap(short, long, conflicts_with = "urls")]
+    input: Option<PathBuf>,
+
+    #[clap(short = 'H', long = "header", value_parser = parse_header)]
+    headers: Vec<(String, String)>,
+
+    #[clap(short, long, default_value = "-")]
+    output: String,
+
+    /// What the load levels control
+    #[clap(long, value_enum, default_value_t = ProfileKind::Concurrency)]
+    load: ProfileKind,
+
+    /// Initial load level
+    #[clap(long, default_value_t = 1)]
+    start: usize,
+
+    /// Maximum load level
+    #[clap(long, default_value_t = 10_000)]
+    max: usize,
+
+    /// Increases the load by this amount at each trial (the load is doubled if omitted)
+    #[clap(long)]
+    step: Option<usize>,
+
+    /// The search stops when the sustainable and the breaching loads are this close
+    #[clap(long, default_value_t = 1)]
+    resolution: usize,
+
+    /// Duration of each trial run
+    #[clap(long, value_parser = parse_duration, default_value = "10s")]
+    trial_duration: Duration,
+
+    /// Latency percentile checked against `--max-latency`
+    #[clap(long, value_parser = parse_percentile, default_value_t = 99.0)]
+    percentile: f64,
+
+    /// Maximum allowed latency at `--percentile` (e.g., "200ms")
+    #[clap(long, value_parser = parse_duration, required_unless_present = "max_error_rate")]
+    max_latency: Option<Duration>,
+
+    /// Maximum allowed error rate in percent
+    #[clap(long)]
+    max_error_rate: Option<f64>,
+
+    /// Options of each trial run (`--concurrency` limits the in-flight requests of rate trials)
+    #[clap(flatten)]
+    runner: RunnerOptions,
+}
+
+impl FindMaxCommand {
+    fn execute(&self) {
+        if self.runner.duration.is_some()
+            || self.runner.stages.is_some()
+            || self.runner.profile.is_some()
+        {
+            eprintln!("Trials are bounded by `--trial-duration` (`--duration`, `--stages` and `--profile` cannot be used)");
+            std::process::exit(1);
+        }
+        let requests = track_try_unwrap!(self.requests());
+        let kind = match self.load {
+            ProfileKind::Concurrency => hb::profile::ProfileKind::Concurrency,
+            ProfileKind::Rate => hb::profile::ProfileKind::Rate,
         };
-        let summary = hb::summary::Summary::new(responses);
+        let limits = hb::saturation::Limits {
+            percentile: self.percentile,
+            max_latency: self.max_latency.map(hb::run::Seconds::from),
+            max_error_rate: self.max_error_rate,
+        };
+        let mut builder = hb::saturation::SaturationSearchBuilder::new(kind);
+        builder
+            .start(self.start)
+            .max(self.max)
+            .resolution(self.resolution)
+            .limits(limits);
+        if let Some(step) = self.step {
+            builder.step(step);
+        }
+
+        let name = match kind {
+            hb::profile::ProfileKind::Concurrency => "concurrency",
+            hb::profile::ProfileKind::Rate => "rate",
+        };
+        let mut interrupted = false;
+        let report = track_try_unwrap!(builder.finish(|load| {
+            let queue = self.queue(requests.clone(), kind, load);
+            let mut runner = track!(self.runner.runner_builder())?;
+            runner.duration(self.trial_duration);
+            match kind {
+                hb::profile::ProfileKind::Concurrency => {
+                    runner.concurrency(load);
+                }
+                hb::profile::ProfileKind::Rate => {
+                    runner.profile(self.rate_profile(load));
+                }
+            }
+            let output = track!(self.runner.execute_with(&runner, &queue, None))?;
+            if let Some(stopped) = &output.stopped {
+                if let hb::run::StopReason::Interrupted = stopped.reason {
+                    interrupted = true;
+                    return Ok(None);
+                }
+                eprintln!("Trial at {} {} aborted: {}", name, load, stopped.reason);
+            }
+            eprintln!(
+                "Trial at {} {}: {} requests",
+                name,
+                load,
+                output.results.len()
+            );
+            Ok(Some(output.results))
+        }));
+        match &report.max_sustainable {
+            Some(m) => eprintln!(
+                "Max sustainable {}: {} ({:.1} req/s, p{} {:.1}ms, {:.2}% errors)",
+                name,
+                m.load,
+                m.rps,
+                self.percentile,
+                m.latency.0 * 1000.0,
+                m.error_rate
+            ),
+            None => eprintln!("No sustainable load found"),
+        }
+
         match self.output.as_str() {
             "-" => {
-                track_try_unwrap!(serdeconv::to_json_writer_pretty(&summary, io::stdout()));
+                track_try_unwrap!(serdeconv::to_json_writer_pretty(&report, io::stdout()));
                 println!();
             }
             filepath => {
                 let f = track_try_unwrap!(File::create(filepath).map_err(Error::from));
-                track_try_unwrap!(serdeconv::to_json_writer_pretty(&summary, f));
+                track_try_unwrap!(serdeconv::to_json_writer_pretty(&report, f));
+            }
+        }
+        if interrupted {
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+    }
+
+    fn requests(&self) -> hb::Result<Vec<hb::request::Request>> {
+        if let Some(path) = &self.input {
+            let f = track!(File::open(path).map_err(Error::from); path)?;
+            let requests: Vec<hb::request::Request> =
+                track!(serdeconv::from_json_reader(BufReader::new(f)); path)?;
+            track_assert!(
+                !requests.is_empty(),
+                hb::ErrorKind::Other,
+                "No requests in {:?}",
+                path
+            );
+            Ok(requests)
+        } else {
+            let headers = track!(hb::request::collect_headers(self.headers.clone()))?;
+            Ok(self
+                .urls
+                .iter()
+                .map(|url| hb::request::Request {
+                    method: hb::request::Method::Get,
+                    url: url.clone(),
+                    content: None,
+                    timeout: None,
+                    start_time: None,
+                    headers: headers.clone(),
+                    expect: None,
+                    name: None,
+                    tags: Vec::new(),
+                })
+                .collect())
+        }
+    }
+
+    /// A profile which keeps the given rate throughout the trial.
+    fn rate_profile(&self, rate: usize) -> hb::profile::Profile {
+        let stage = |duration| hb::profile::Stage {
+            name: None,
+            duration,
+            target: rate as f64,
+        };
+        hb::profile::Profile {
+            kind: hb::profile::ProfileKind::Rate,
+            stages: vec![
+                stage(hb::run::Seconds(0.0)),
+                stage(self.trial_duration.into()),
+            ],
+        }
+    }
+
+    fn queue(
+        &self,
+        requests: Vec<hb::request::Request>,
+        kind: hb::profile::ProfileKind,
+        load: usize,
+    ) -> hb::run::RequestQueue {
+        let requests = requests.into_iter().cycle().map(|mut r| {
+            r.start_time = None;
+            r
+        });
+        match kind {
+            hb::profile::ProfileKind::Concurrency => {
+                hb::run::RequestQueue::with_generator(requests)
+            }
+            hb::profile::ProfileKind::Rate => {
+                let times = self.rate_profile(load).start_times();
+                hb::run::RequestQueue::with_generator(requests.zip(times).map(|(mut r, t)| {
+                    r.start_time = Some(t);
+                    r
+                }))
             }
         }
     }
@@ -303,23 +1296,93 @@ struct TimeSeriesCommand {
     #[clap(short, long, default_value = "-")]
     input: String,
 
+    /// Format of the input result files
+    #[clap(long, value_enum, default_value_t = ResultFormat::Json)]
+    input_format: ResultFormat,
+
     #[clap(short, long, default_value = "-")]
     output: String,
+
+    /// Width of each bucket (e.g., "100ms", "10s")
+    #[clap(long, value_parser = parse_interval, default_value = "1s")]
+    interval: Duration,
+
+    /// Which time of a request determines its bucket
+    #[clap(long, value_enum, default_value_t = BucketBy::Start)]
+    bucket_by: BucketBy,
+
+    /// Latency percentiles to report for each bucket
+    #[clap(long, value_delimiter = ',', value_parser = parse_percentile)]
+    percentiles: Vec<f64>,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum BucketBy {
+    Start,
+    End,
 }
 
 impl TimeSeriesCommand {
     fn execute(&self) {
-        let responses = match self.input.as_str() {
+        let responses = track_try_unwrap!(read_results(&self.input, self.input_format));
+        let bucket_by = match self.bucket_by {
+            BucketBy::Start => hb::time_series::BucketBy::StartTime,
+            BucketBy::End => hb::time_series::BucketBy::EndTime,
+        };
+        let summary = hb::time_series::TimeSeriesBuilder::new()
+            .interval(self.interval)
+            .bucket_by(bucket_by)
+            .percentiles(self.percentiles.clone())
+            .finish(responses);
+        match self.output.as_str() {
             "-" => {
-                let stdin = io::stdin();
-                track_try_unwrap!(serdeconv::from_json_reader(stdin.lock()))
+                track_try_unwrap!(serdeconv::to_json_writer_pretty(&summary, io::stdout()));
+                println!();
             }
             filepath => {
-                let f = track_try_unwrap!(File::open(filepath).map_err(Error::from));
-                track_try_unwrap!(serdeconv::from_json_reader(BufReader::new(f)))
+                let f = track_try_unwrap!(File::create(filepath).map_err(Error::from));
+                track_try_unwrap!(serdeconv::to_json_writer_pretty(&summary, f));
             }
-        };
-        let summary = hb::time_series::TimeSeries::new(responses);
+        }
+    }
+}
+
+/// Merges HdrHistogram interval logs (e.g., written by `--hdr-log` of multiple runs) into a latency summary
+#[derive(clap::Args)]
+struct HdrSummaryCommand {
+    /// Interval log files ("-" for stdin)
+    #[clap(required = true)]
+    inputs: Vec<String>,
+
+    #[clap(short, long, default_value = "-")]
+    output: String,
+
+    /// Latency percentiles to report
+    #[clap(
+        long,
+        value_delimiter = ',',
+        value_parser = parse_percentile,
+        default_value = "50,75,90,95,99,99.9,99.99"
+    )]
+    percentiles: Vec<f64>,
+}
+
+impl HdrSummaryCommand {
+    fn execute(&self) {
+        let mut intervals = Vec::new();
+        for input in &self.inputs {
+            let log = match input.as_str() {
+                "-" => track_try_unwrap!(hb::histogram::read_interval_log(io::stdin())),
+                filepath => {
+                    let f = track_try_unwrap!(File::open(filepath).map_err(Error::from));
+                    track_try_unwrap!(hb::histogram::read_interval_log(f))
+                }
+            };
+            intervals.extend(log);
+        }
+        let summary = track_try_unwrap!(hb::summary::SummaryBuilder::new()
+            .percentiles(self.percentiles.clone())
+            .finish_histograms(&intervals));
         match self.output.as_str() {
             "-" => {
                 track_try_unwrap!(serdeconv::to_json_writer_pretty(&summary, io::stdout()));
@@ -332,3 +1395,45 @@ impl TimeSeriesCommand {
         }
     }
 }
+
+/// Merges the result files of multiple runs (e.g., by load generators on different hosts) into one
+///
+/// The times are aligned by the start time of each run, and `seq_no` is renumbered in order of the start times.
+#[derive(clap::Args)]
+struct MergeCommand {
+    /// Result files ("-" for stdin)
+    #[clap(required = true)]
+    inputs: Vec<String>,
+
+    /// Format of the input result files
+    #[clap(long, value_enum, default_value_t = ResultFormat::Json)]
+    input_format: ResultFormat,
+
+    #[clap(flatten)]
+    output: ResultOutputOptions,
+
+    /// Regards the files without a start time (written by older versions) as started with the earliest run
+    #[clap(long)]
+    allow_missing_anchors: bool,
+}
+
+impl MergeCommand {
+    fn execute(&self) {
+        if self.inputs.iter().filter(|i| *i == "-").count() > 1 {
+            eprintln!("Stdin (\"-\") can be given only once");
+            std::process::exit(1);
+        }
+        let mut files = Vec::new();
+        for input in &self.inputs {
+            let records = track_try_unwrap!(read_records(input, self.input_format));
+            files.push(hb::merge::ResultFile {
+                source: input.clone(),
+                records,
+            });
+        }
+        let records = track_try_unwrap!(hb::merge::MergeBuilder::new()
+            .allow_missing_anchors(self.allow_missing_anchors)
+            .finish(files));
+        track_try_unwrap!(self.output.write_records(&records));
+    }
+}
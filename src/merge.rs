@@ -0,0 +1,154 @@
+//! Merging of the result files of multiple runs (e.g., by load generators on different hosts).
+use crate::run::{Record, RequestResult, Seconds, Started, Stopped};
+use crate::{ErrorKind, Result};
+
+/// The records of a result file.
+#[derive(Debug, Clone)]
+pub struct ResultFile {
+    /// The name recorded in `Labels::source` of the merged results (e.g., the path of the file).
+    pub source: String,
+    pub records: Vec<Record>,
+}
+
+/// Merges result files into one whose times are relative to the earliest start among the runs.
+///
+/// The times of each file are aligned by its `Started` marker, and the results are renumbered
+/// in order of their start times. Each result is labeled with its source (unless it already has one,
+/// which is the case for results of merged files).
+#[derive(Debug, Clone, Default)]
+pub struct MergeBuilder {
+    allow_missing_anchors: bool,
+}
+impl MergeBuilder {
+    pub fn new() -> Self {
+        MergeBuilder::default()
+    }
+
+    /// Makes files without a `Started` marker (e.g., written by older versions) regarded as
+    /// started at the same time as the earliest run, instead of being rejected.
+    ///
+    /// The default value is `false`.
+    pub fn allow_missing_anchors(&mut self, allow: bool) -> &mut Self {
+        self.allow_missing_anchors = allow;
+        self
+    }
+
+    /// Returns the merged records: the `Started` marker (if any of the files has one),
+    /// the results sorted by `seq_no`, and then the `Stopped` markers of the runs.
+    pub fn finish(&self, files: Vec<ResultFile>) -> Result<Vec<Record>> {
+        let mut anchors = Vec::new();
+        for file in &files {
+            let anchor = file.records.iter().find_map(|r| match r {
+                Record::Started(s) => Some(s.unix_time),
+                _ => None,
+            });
+            track_assert!(
+                anchor.is_some() || self.allow_missing_anchors,
+                ErrorKind::Other,
+                "No start time in {:?} (written by an older version?)",
+                file.source
+            );
+            anchors.push(anchor);
+        }
+        let base = anchors.iter().flatten().min().cloned();
+
+        let mut results = Vec::new();
+        let mut stopped = Vec::new();
+        for (ResultFile { source, records }, anchor) in files.into_iter().zip(anchors) {
+            let offset = match (anchor, base) {
+                (Some(anchor), Some(base)) => Seconds(anchor.0 - base.0),
+                _ => Seconds(0.0),
+            };
+            for record in records {
+                match record {
+                    Record::Result(mut r) => {
+                        let seq_no = r.seq_no();
+                        r.rebase(seq_no, offset);
+                        r.labels_mut().source.get_or_insert_with(|| source.clone());
+                        results.push(r);
+                    }
+                    Record::Stopped(s) => stopped.push(Stopped {
+                        end_time: Seconds(s.end_time.0 + offset.0),
+                        reason: s.reason,
+                    }),
+                    Record::Started(_) => {}
+                }
+            }
+        }
+
+        // NOTE: The sort is stable, so ties are kept in the order of the files and their `seq_no`.
+        results.sort_by_key(RequestResult::start_time);
+        stopped.sort_by_key(|s| s.end_time);
+
+        let mut records = Vec::with_capacity(results.len() + stopped.len() + 1);
+        records.extend(base.map(|unix_time| Record::Started(Started { unix_time })));
+        records.extend(results.into_iter().enumerate().map(|(seq_no, mut r)| {
+            r.rebase(seq_no, Seconds(0.0));
+            Record::Result(r)
+        }));
+        records.extend(stopped.into_iter().map(Record::Stopped));
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn file(source: &str, unix_time: Option<f64>, end_times: &[f64]) -> ResultFile {
+        let mut records = unix_time
+            .map(|t| {
+                Record::Started(Started {
+                    unix_time: Seconds(t),
+                })
+            })
+            .into_iter()
+            .collect::<Vec<_>>();
+        records.extend(
+            end_times
+                .iter()
+                .enumerate()
+                .map(|(seq_no, &t)| Record::Result(RequestResult::ok_for_test(seq_no, t, 0.5))),
+        );
+        ResultFile {
+            source: source.to_owned(),
+            records,
+        }
+    }
+
+    #[test]
+    fn merge_works() {
+        let files = vec![
+            file("a", Some(1000.0), &[1.0, 3.0]),
+            file("b", Some(998.5), &[1.0, 2.0]),
+        ];
+        let records = MergeBuilder::new().finish(files).unwrap();
+        assert!(matches!(records[0], Record::Started(ref s) if s.unix_time == Seconds(998.5)));
+
+        let results = records[1..]
+            .iter()
+            .map(|r| match r {
+                Record::Result(r) => (r.seq_no(), r.end_time().0, r.labels().source.clone()),
+                _ => panic!(),
+            })
+            .collect::<Vec<_>>();
+        let source = |s: &str| Some(s.to_owned());
+        assert_eq!(
+            results,
+            [
+                (0, 1.0, source("b")),
+                (1, 2.0, source("b")),
+                (2, 2.5, source("a")),
+                (3, 4.5, source("a")),
+            ]
+        );
+
+        assert!(MergeBuilder::new()
+            .finish(vec![file("c", None, &[1.0])])
+            .is_err());
+        assert!(MergeBuilder::new()
+            .allow_missing_anchors(true)
+            .finish(vec![file("c", None, &[1.0])])
+            .is_ok());
+    }
+}
@@ -0,0 +1,185 @@
+//! Weighted mixes of requests (e.g., 70% reads, 25% searches and 5% writes).
+use crate::request::Request;
+use crate::run::DEFAULT_REQUEST_COUNT;
+use crate::template::Rng;
+use crate::{ErrorKind, Result};
+use serde::Deserialize;
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The format of mix files.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MixFile {
+    /// Seed used to sample the entries.
+    #[serde(default)]
+    pub seed: Option<u64>,
+
+    /// Number of requests to generate.
+    #[serde(default)]
+    pub count: Option<usize>,
+
+    pub entries: Vec<MixEntry>,
+}
+impl MixFile {
+    pub fn read_from<R: Read>(reader: R) -> Result<Self> {
+        let file = track!(serdeconv::from_json_reader(reader))?;
+        Ok(file)
+    }
+}
+
+/// A request and its relative frequency in the mix.
+///
+/// Unnamed entries are named after their method and URL (e.g., `"GET http://localhost/items"`)
+/// so that the summary can be broken down by entry.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MixEntry {
+    pub weight: f64,
+    pub request: Request,
+}
+
+/// An iterator which samples requests from the entries of a mix at random.
+#[derive(Debug)]
+pub struct MixGenerator {
+    requests: Vec<Request>,
+    cumulative_weights: Vec<f64>,
+    rng: Rng,
+    seed: u64,
+    remaining: Option<usize>,
+}
+impl MixGenerator {
+    /// Makes a new generator.
+    ///
+    /// If `seed` is `None`, the seed of `file` (or the current time if it is also `None`) is used.
+    /// If `count` is `None`, the count of `file` is used, and if it is also `None`,
+    /// `DEFAULT_REQUEST_COUNT` requests are generated unless `unlimited` is `true`.
+    pub fn new(
+        file: MixFile,
+        seed: Option<u64>,
+        count: Option<usize>,
+        unlimited: bool,
+    ) -> Result<Self> {
+        track_assert!(!file.entries.is_empty(), ErrorKind::Other, "No entries");
+        let mut requests = Vec::new();
+        let mut cumulative_weights = Vec::new();
+        let mut total = 0.0;
+        for mut entry in file.entries {
+            track_assert!(
+                entry.weight >= 0.0 && entry.weight.is_finite(),
+                ErrorKind::Other,
+                "Invalid weight: {}",
+                entry.weight
+            );
+            if entry.request.name.is_none() {
+                let name = format!("{} {}", entry.request.method, entry.request.url);
+                entry.request.name = Some(name);
+            }
+            total += entry.weight;
+            cumulative_weights.push(total);
+            requests.push(entry.request);
+        }
+        track_assert!(total > 0.0, ErrorKind::Other, "All weights are zero");
+
+        let seed = seed.or(file.seed).unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64)
+        });
+        let remaining = count.or(file.count).or(if unlimited {
+            None
+        } else {
+            Some(DEFAULT_REQUEST_COUNT)
+        });
+        Ok(MixGenerator {
+            requests,
+            cumulative_weights,
+            rng: Rng::new(seed),
+            seed,
+            remaining,
+        })
+    }
+
+    /// Returns the seed used to sample the entries.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+impl Iterator for MixGenerator {
+    type Item = Request;
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(remaining) = self.remaining.as_mut() {
+            if *remaining == 0 {
+                return None;
+            }
+            *remaining -= 1;
+        }
+        let total = *self.cumulative_weights.last().expect("Never fails");
+
+        // A uniform value in `[0, total)` from the upper 53 bits.
+        let x = (self.rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64 * total;
+        let i = self.cumulative_weights.partition_point(|&w| w <= x);
+        Some(self.requests[i.min(self.requests.len() - 1)].clone())
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.remaining {
+            Some(n) => (n, Some(n)),
+            None => (0, None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mix_generator_works() {
+        let file = MixFile::read_from(
+            r#"{"entries": [
+                 {"weight": 70, "request": {"method": "GET", "url": "http://localhost/items", "name": "items"}},
+                 {"weight": 30, "request": {"method": "POST", "url": "http://localhost/items", "content": "{}"}},
+                 {"weight": 0, "request": {"method": "DELETE", "url": "http://localhost/items"}}
+               ]}"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let requests = MixGenerator::new(file, Some(1), Some(10_000), false)
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(requests.len(), 10_000);
+
+        let count = |name: &str| {
+            requests
+                .iter()
+                .filter(|r| r.name.as_deref() == Some(name))
+                .count()
+        };
+        assert!((6800..7200).contains(&count("items")));
+        assert_eq!(
+            count("items") + count("POST http://localhost/items"),
+            10_000
+        );
+    }
+
+    #[test]
+    fn misspelled_fields_are_rejected() {
+        let file = r#"{"entries": [
+                        {"wieght": 1, "request": {"method": "GET", "url": "http://localhost/"}}
+                      ]}"#;
+        assert!(MixFile::read_from(file.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn default_count_is_shared_with_plain_runs() {
+        let file = MixFile::read_from(
+            r#"{"entries": [{"weight": 1, "request": {"method": "GET", "url": "http://localhost/"}}]}"#
+                .as_bytes(),
+        )
+        .unwrap();
+        let generator = MixGenerator::new(file.clone(), None, None, false).unwrap();
+        assert_eq!(generator.count(), DEFAULT_REQUEST_COUNT);
+        let generator = MixGenerator::new(file, None, None, true).unwrap();
+        assert_eq!(generator.size_hint(), (0, None));
+    }
+}
@@ -0,0 +1,220 @@
+//! Multi-stage load profiles.
+use crate::run::Seconds;
+use crate::{Error, ErrorKind, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::str::FromStr;
+use trackable::error::ErrorKindExt;
+
+/// Maximum target of concurrency profiles (i.e., the number of clients spawned by a run).
+pub const MAX_CONCURRENCY: f64 = 100_000.0;
+
+/// What the targets of the stages control.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileKind {
+    /// Number of concurrent clients.
+    Concurrency,
+
+    /// Requests per second.
+    Rate,
+}
+
+/// A stage moves the load linearly from the target of the previous stage (or zero)
+/// to its own target over its duration.
+///
+/// A stage whose duration is zero changes the load in a single step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stage {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub duration: Seconds,
+    pub target: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub kind: ProfileKind,
+    pub stages: Vec<Stage>,
+}
+impl Profile {
+    pub fn read_from<R: Read>(reader: R) -> Result<Self> {
+        let profile: Self = track!(serdeconv::from_json_reader(reader))?;
+        track!(profile.validate())?;
+        Ok(profile)
+    }
+
+    /// Parses stages such as `"0s:10,2m:500:ramp-up,10m:500,0s:10"` (`DURATION:TARGET[:NAME]`).
+    pub fn parse_stages(kind: ProfileKind, s: &str) -> Result<Self> {
+        let mut stages = Vec::new();
+        for stage in s.split(',') {
+            let mut tokens = stage.splitn(3, ':');
+            let duration = tokens.next().unwrap_or_default();
+            let duration = track!(Seconds::from_str(duration); stage)?;
+            let target = track_assert_some!(tokens.next(), ErrorKind::Other; stage);
+            let target = f64::from_str(target).map_err(|e| Error::from(ErrorKind::Other.cause(e)));
+            let target = track!(target; stage)?;
+            let name = tokens.next().map(|n| n.to_owned());
+            stages.push(Stage {
+                name,
+                duration,
+                target,
+            });
+        }
+        let profile = Profile { kind, stages };
+        track!(profile.validate())?;
+        Ok(profile)
+    }
+
+    fn validate(&self) -> Result<()> {
+        track_assert!(!self.stages.is_empty(), ErrorKind::Other, "No stages");
+        for stage in &self.stages {
+            track_assert!(stage.duration.0 >= 0.0, ErrorKind::Other; stage);
+            track_assert!(stage.duration.0.is_finite(), ErrorKind::Other; stage);
+            track_assert!(stage.target >= 0.0, ErrorKind::Other; stage);
+            track_assert!(stage.target.is_finite(), ErrorKind::Other; stage);
+            if self.kind == ProfileKind::Concurrency {
+                track_assert!(
+                    stage.target <= MAX_CONCURRENCY,
+                    ErrorKind::Other,
+                    "Too large concurrency: {}",
+                    stage.target
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the total duration of the stages.
+    pub fn duration(&self) -> Seconds {
+        Seconds(self.stages.iter().map(|s| s.duration.0).sum())
+    }
+
+    /// Returns the maximum target of the stages.
+    pub fn max_target(&self) -> f64 {
+        self.stages.iter().map(|s| s.target).fold(0.0, f64::max)
+    }
+
+    /// Returns the load (i.e., the concurrency or the rate) at the given time.
+    pub fn target_at(&self, time: Seconds) -> f64 {
+        let mut start = 0.0;
+        let mut from = 0.0;
+        for stage in &self.stages {
+            let end = start + stage.duration.0;
+            if time.0 < end {
+                return from + (stage.target - from) * (time.0 - start) / stage.duration.0;
+            }
+            start = end;
+            from = stage.target;
+        }
+        from
+    }
+
+    /// Returns the name of the stage at the given time.
+    ///
+    /// Unnamed stages are called `stage1`, `stage2` and so on.
+    /// Times after the last stage belong to the last stage.
+    pub fn stage_name_at(&self, time: Seconds) -> String {
+        let mut end = 0.0;
+        let mut index = self.stages.len() - 1;
+        for (i, stage) in self.stages.iter().enumerate() {
+            end += stage.duration.0;
+            if time.0 < end {
+                index = i;
+                break;
+            }
+        }
+        self.stages[index]
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("stage{}", index + 1))
+    }
+
+    /// Returns the start times of the requests issued at the rate given by this profile.
+    pub fn start_times(&self) -> StartTimes {
+        StartTimes {
+            stages: self.stages.clone(),
+            stage_index: 0,
+            stage_start: 0.0,
+            stage_from: 0.0,
+            count_before_stage: 0.0,
+            next: 0,
+        }
+    }
+}
+
+/// An iterator which yields the start times of requests following a rate profile.
+///
+/// The `n`-th request (zero origin) starts when the integral of the rate reaches `n`.
+#[derive(Debug)]
+pub struct StartTimes {
+    stages: Vec<Stage>,
+    stage_index: usize,
+    stage_start: f64,
+    stage_from: f64,
+    count_before_stage: f64,
+    next: usize,
+}
+impl Iterator for StartTimes {
+    type Item = Seconds;
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(stage) = self.stages.get(self.stage_index) {
+            let d = stage.duration.0;
+            let a = self.stage_from;
+            let b = if d > 0.0 { (stage.target - a) / d } else { 0.0 };
+            let need = self.next as f64 - self.count_before_stage;
+
+            // Solves `a * t + b * t^2 / 2 = need`.
+            let t = if b.abs() < f64::EPSILON {
+                if a > 0.0 {
+                    need / a
+                } else {
+                    f64::INFINITY
+                }
+            } else {
+                (-a + (a * a + 2.0 * b * need).max(0.0).sqrt()) / b
+            };
+            if t.is_finite() && t >= 0.0 && t < d {
+                self.next += 1;
+                return Some(Seconds(self.stage_start + t));
+            }
+
+            self.count_before_stage += a * d + b * d * d / 2.0;
+            self.stage_start += d;
+            self.stage_from = stage.target;
+            self.stage_index += 1;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rate_profile_works() {
+        let profile = Profile::parse_stages(ProfileKind::Rate, "0s:10,1s:10,2s:30,1s:0").unwrap();
+        assert_eq!(profile.duration(), Seconds(4.0));
+        assert_eq!(profile.target_at(Seconds(0.5)), 10.0);
+        assert_eq!(profile.target_at(Seconds(2.0)), 20.0);
+        assert_eq!(profile.stage_name_at(Seconds(0.0)), "stage2");
+        assert_eq!(profile.stage_name_at(Seconds(9.0)), "stage4");
+
+        // 10 requests in the 1st second, 40 in the ramp (2s) and 15 in the last (1s).
+        let times = profile.start_times().collect::<Vec<_>>();
+        assert_eq!(times.len(), 65);
+        assert_eq!(times[1], Seconds(0.1));
+        assert!(times.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(times.last().unwrap().0 < 4.0);
+    }
+
+    #[test]
+    fn invalid_targets_are_rejected() {
+        assert!(Profile::parse_stages(ProfileKind::Rate, "1s:inf").is_err());
+        assert!(Profile::parse_stages(ProfileKind::Rate, "1s:NaN").is_err());
+        assert!(Profile::parse_stages(ProfileKind::Rate, "1s:1e9").is_ok());
+        assert!(Profile::parse_stages(ProfileKind::Concurrency, "1s:1e9").is_err());
+        assert!(Profile::parse_stages(ProfileKind::Concurrency, "1s:100").is_ok());
+    }
+}
@@ -0,0 +1,236 @@
+//! Periodic progress reports of a running benchmark.
+use crate::histogram::LatencyHistogram;
+use crate::run::{RequestResult, Seconds};
+use crate::summary::Percentiles;
+use crate::{Error, Result};
+use fibers::time::timer;
+use futures::{Async, Future};
+use serde::Serialize;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Latency percentiles reported in each progress line.
+const PERCENTILES: [f64; 2] = [50.0, 99.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressFormat {
+    /// A human readable line.
+    Text,
+
+    /// A JSON object (i.e., `Progress`) per line.
+    Json,
+}
+
+/// A progress report.
+///
+/// `rps` and `latency` are computed from the requests completed since the previous report.
+#[derive(Debug, Clone, Serialize)]
+pub struct Progress {
+    pub elapsed: Seconds,
+    pub completed: usize,
+
+    /// Total number of requests (`None` if unknown).
+    pub total: Option<usize>,
+    pub rps: f64,
+
+    /// Number of the requests that did not succeed (i.e., errors and assertion failures) so far.
+    pub errors: usize,
+    pub latency: Percentiles,
+}
+
+pub(crate) struct ProgressReporter {
+    writer: Box<dyn Write + Send>,
+    format: ProgressFormat,
+    interval: Duration,
+    timer: timer::Timeout,
+    bench_start: Instant,
+    window_start: Instant,
+    window: LatencyHistogram,
+    completed: usize,
+    errors: usize,
+}
+impl ProgressReporter {
+    pub fn new(
+        writer: Box<dyn Write + Send>,
+        format: ProgressFormat,
+        interval: Duration,
+        bench_start: Instant,
+    ) -> Self {
+        ProgressReporter {
+            writer,
+            format,
+            interval,
+            timer: timer::timeout(interval),
+            bench_start,
+            window_start: Instant::now(),
+            window: LatencyHistogram::new(),
+            completed: 0,
+            errors: 0,
+        }
+    }
+
+    pub fn record(&mut self, result: &RequestResult) {
+        self.completed += 1;
+        if !result.is_ok() {
+            self.errors += 1;
+        }
+        self.window.record(result.elapsed());
+    }
+
+    /// Writes a report if the interval has elapsed.
+    pub fn poll(&mut self, total: Option<usize>) -> Result<()> {
+        while let Async::Ready(()) = track!(self.timer.poll().map_err(Error::from))? {
+            track!(self.report(total))?;
+            self.timer = timer::timeout(self.interval);
+        }
+        Ok(())
+    }
+
+    pub fn report(&mut self, total: Option<usize>) -> Result<()> {
+        let window_secs = self.window_start.elapsed().as_secs_f64();
+        let progress = Progress {
+            elapsed: self.bench_start.elapsed().into(),
+            completed: self.completed,
+            total,
+            rps: if window_secs > 0.0 {
+                self.window.len() as f64 / window_secs
+            } else {
+                0.0
+            },
+            errors: self.errors,
+            latency: Percentiles(if self.window.is_empty() {
+                Vec::new()
+            } else {
+                PERCENTILES
+                    .iter()
+                    .map(|&p| (p, self.window.percentile(p)))
+                    .collect()
+            }),
+        };
+        self.window.reset();
+        self.window_start = Instant::now();
+
+        match self.format {
+            ProgressFormat::Text => {
+                let total = progress.total.map_or("?".to_owned(), |n| n.to_string());
+                let mut line = format!(
+                    "[{:8.1}s] {}/{} requests, {:.1} req/s, {} errors",
+                    progress.elapsed.0, progress.completed, total, progress.rps, progress.errors
+                );
+                for (p, latency) in &progress.latency.0 {
+                    line += &format!(", p{} {:.1}ms", p, latency.0 * 1000.0);
+                }
+                track!(writeln!(self.writer, "{}", line).map_err(Error::from))?;
+            }
+            ProgressFormat::Json => {
+                track!(serdeconv::to_json_writer(&progress, &mut self.writer))?;
+                track!(self.writer.write_all(b"\n").map_err(Error::from))?;
+            }
+        }
+        track!(self.writer.flush().map_err(Error::from))?;
+        Ok(())
+    }
+}
+impl std::fmt::Debug for ProgressReporter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ProgressReporter")
+            .field("format", &self.format)
+            .field("interval", &self.interval)
+            .field("completed", &self.completed)
+            .field("errors", &self.errors)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn reporter(format: ProgressFormat) -> (ProgressReporter, SharedBuf) {
+        let buf = SharedBuf::default();
+        let reporter = ProgressReporter::new(
+            Box::new(buf.clone()),
+            format,
+            Duration::from_secs(60),
+            Instant::now(),
+        );
+        (reporter, buf)
+    }
+
+    fn lines(buf: &SharedBuf) -> Vec<serde_json::Value> {
+        String::from_utf8(buf.0.lock().unwrap().clone())
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn json_progress_works() {
+        let (mut reporter, buf) = reporter(ProgressFormat::Json);
+        for i in 1..=100 {
+            reporter.record(&RequestResult::ok_for_test(i, 1.0, i as f64 / 1000.0));
+        }
+        reporter.report(Some(200)).unwrap();
+
+        let lines = lines(&buf);
+        assert_eq!(lines.len(), 1);
+        let line = &lines[0];
+        assert_eq!(line["completed"], 100);
+        assert_eq!(line["total"], 200);
+        assert_eq!(line["errors"], 0);
+        assert!(line["rps"].as_f64().unwrap() > 0.0);
+        assert!((line["latency"]["p50"].as_f64().unwrap() - 0.05).abs() < 0.001);
+        assert!((line["latency"]["p99"].as_f64().unwrap() - 0.099).abs() < 0.001);
+    }
+
+    #[test]
+    fn latency_window_is_rolled() {
+        let (mut reporter, buf) = reporter(ProgressFormat::Json);
+        reporter.record(&RequestResult::ok_for_test(0, 1.0, 0.5));
+        reporter.report(None).unwrap();
+        reporter.report(None).unwrap();
+        reporter.record(&RequestResult::ok_for_test(1, 2.0, 0.01));
+        reporter.report(None).unwrap();
+
+        let lines = lines(&buf);
+        assert_eq!(lines.len(), 3);
+        assert!((lines[0]["latency"]["p99"].as_f64().unwrap() - 0.5).abs() < 0.001);
+
+        // Nothing was completed during the second window.
+        assert_eq!(lines[1]["completed"], 1);
+        assert_eq!(lines[1]["rps"], 0.0);
+        assert_eq!(lines[1]["latency"], serde_json::json!({}));
+
+        // The latency of the first window is not included in the third.
+        assert_eq!(lines[2]["completed"], 2);
+        assert!((lines[2]["latency"]["p99"].as_f64().unwrap() - 0.01).abs() < 0.001);
+        assert_eq!(lines[2]["total"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn text_progress_works() {
+        let (mut reporter, buf) = reporter(ProgressFormat::Text);
+        reporter.record(&RequestResult::ok_for_test(0, 1.0, 0.002));
+        reporter.report(None).unwrap();
+        let text = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        assert!(text.contains(" 1/? requests, "), "{}", text);
+        assert!(
+            text.ends_with(", 0 errors, p50 2.0ms, p99 2.0ms\n"),
+            "{}",
+            text
+        );
+    }
+}
@@ -0,0 +1,357 @@
+//! Conversion of recorded traffic (HAR files and access logs) into requests.
+use crate::request::{Content, Method, Request};
+use crate::run::Seconds;
+use crate::{Error, ErrorKind, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Read};
+use trackable::error::ErrorKindExt;
+use url::Url;
+
+/// If `speed` is `Some(_)`, the original inter-arrival times divided by `speed` are kept
+/// as the start times of the requests.
+///
+/// Entries that cannot be converted (e.g., with an unsupported URL) are skipped.
+pub fn read_har<R: Read>(reader: R, speed: Option<f64>) -> Result<Vec<Request>> {
+    let har: Har = track!(serdeconv::from_json_reader(reader))?;
+    let mut requests = Vec::new();
+    for (i, entry) in har.log.entries.into_iter().enumerate() {
+        match parse_har_entry(entry) {
+            Ok(entry) => requests.push(entry),
+            Err(e) => log::warn!("Skips entry {}: {}", i, e),
+        }
+    }
+    Ok(set_start_times(requests, speed))
+}
+
+fn parse_har_entry(entry: HarEntry) -> Result<(f64, Request)> {
+    let url = track!(Url::parse(&entry.request.url).map_err(Error::from); entry.request.url)?;
+    track_assert!(
+        url.scheme() == "http" || url.scheme() == "https",
+        ErrorKind::Other,
+        "Unsupported URL: {}",
+        url
+    );
+    let time = track!(parse_rfc3339(&entry.started_date_time); entry.started_date_time)?;
+    let method = track!(entry.request.method.parse::<Method>())?;
+    let mut headers = BTreeMap::<String, String>::new();
+    for h in entry.request.headers {
+        if !is_replayable_header(&h.name, &h.value) {
+            continue;
+        }
+
+        // Repeated headers are combined into one since `Request::headers` is a map.
+        match headers
+            .keys()
+            .find(|k| k.eq_ignore_ascii_case(&h.name))
+            .cloned()
+        {
+            Some(name) => {
+                let separator = if name.eq_ignore_ascii_case("cookie") {
+                    "; "
+                } else {
+                    ", "
+                };
+                let value = headers.get_mut(&name).expect("Never fails");
+                value.push_str(separator);
+                value.push_str(&h.value);
+            }
+            None => {
+                headers.insert(h.name, h.value);
+            }
+        }
+    }
+    let request = Request {
+        method,
+        url,
+        content: entry.request.post_data.map(|d| Content::Text(d.text)),
+        timeout: None,
+        start_time: None,
+        headers,
+        expect: None,
+        name: None,
+        tags: Vec::new(),
+    };
+    Ok((time, request))
+}
+
+/// Reads an access log in the Common (or Combined) Log Format.
+///
+/// Since the logs have no scheme and host, the request paths are resolved against `base_url`.
+/// Lines that cannot be parsed are skipped.
+pub fn read_access_log<R: BufRead>(
+    reader: R,
+    base_url: &Url,
+    speed: Option<f64>,
+) -> Result<Vec<Request>> {
+    let mut requests = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = track!(line.map_err(Error::from))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_access_log_line(&line, base_url) {
+            Ok(entry) => requests.push(entry),
+            Err(e) => log::warn!("Skips line {}: {}", i + 1, e),
+        }
+    }
+    Ok(set_start_times(requests, speed))
+}
+
+fn parse_access_log_line(line: &str, base_url: &Url) -> Result<(f64, Request)> {
+    let time_start = track_assert_some!(line.find('['), ErrorKind::Other, "No timestamp");
+    let time_end = track_assert_some!(
+        line[time_start..].find(']'),
+        ErrorKind::Other,
+        "No timestamp"
+    ) + time_start;
+    let time = track!(parse_clf_time(&line[time_start + 1..time_end]))?;
+
+    let rest = &line[time_end + 1..];
+    let request_start = track_assert_some!(rest.find('"'), ErrorKind::Other, "No request line");
+    let request_end = track_assert_some!(
+        rest[request_start + 1..].find('"'),
+        ErrorKind::Other,
+        "No request line"
+    ) + request_start
+        + 1;
+    let mut tokens = rest[request_start + 1..request_end].split_whitespace();
+    let method = track_assert_some!(tokens.next(), ErrorKind::Other, "No method");
+    let path = track_assert_some!(tokens.next(), ErrorKind::Other, "No path");
+    let url = track!(base_url.join(path).map_err(Error::from); path)?;
+    let request = Request {
+        method: track!(method.parse())?,
+        url,
+        content: None,
+        timeout: None,
+        start_time: None,
+        headers: BTreeMap::new(),
+        expect: None,
+        name: None,
+        tags: Vec::new(),
+    };
+    Ok((time, request))
+}
+
+fn set_start_times(mut requests: Vec<(f64, Request)>, speed: Option<f64>) -> Vec<Request> {
+    if let Some(speed) = speed {
+        requests.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let base = requests.first().map_or(0.0, |r| r.0);
+        for (time, request) in &mut requests {
+            request.start_time = Some(Seconds((*time - base) / speed));
+        }
+    }
+    requests.into_iter().map(|(_, r)| r).collect()
+}
+
+fn is_replayable_header(name: &str, value: &str) -> bool {
+    // These are derived from the request itself (and pseudo-headers such as `:authority` are HTTP/2 only).
+    const DERIVED: [&str; 4] = ["host", "content-length", "connection", "transfer-encoding"];
+    if name.starts_with(':') || DERIVED.iter().any(|h| name.eq_ignore_ascii_case(h)) {
+        return false;
+    }
+    if crate::request::validate_header(name, value).is_err() {
+        log::warn!("Skips a header that cannot be sent: {}: {}", name, value);
+        return false;
+    }
+    true
+}
+
+/// Parses a timestamp such as `2024-01-02T03:04:05.678+09:00` into seconds since the UNIX epoch.
+fn parse_rfc3339(s: &str) -> Result<f64> {
+    track_assert!(
+        s.is_ascii() && s.len() >= 19,
+        ErrorKind::Other,
+        "Invalid timestamp: {:?}",
+        s
+    );
+    let date = track!(parse_numbers(&s[..10], '-'))?;
+    let time = track!(parse_numbers(&s[11..19], ':'))?;
+    track_assert_eq!(date.len(), 3, ErrorKind::Other);
+    track_assert_eq!(time.len(), 3, ErrorKind::Other);
+
+    let mut rest = &s[19..];
+    let mut fraction = 0.0;
+    if rest.starts_with('.') {
+        let n = rest[1..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(rest.len(), |i| i + 1);
+        fraction = track!(rest[..n]
+            .parse::<f64>()
+            .map_err(|e| Error::from(ErrorKind::Other.cause(e))))?;
+        rest = &rest[n..];
+    }
+    let offset = if rest == "Z" || rest.is_empty() {
+        0
+    } else {
+        track!(parse_utc_offset(rest))?
+    };
+    Ok(
+        to_unix_time(date[0], date[1], date[2], time[0], time[1], time[2], offset) as f64
+            + fraction,
+    )
+}
+
+/// Parses a timestamp such as `10/Oct/2000:13:55:36 -0700` into seconds since the UNIX epoch.
+fn parse_clf_time(s: &str) -> Result<f64> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (datetime, offset) = track_assert_some!(s.split_once(' '), ErrorKind::Other; s);
+    let mut parts = datetime.splitn(4, ['/', ':']);
+    let day = parts.next().unwrap_or_default();
+    let month = parts.next().unwrap_or_default();
+    let year = parts.next().unwrap_or_default();
+    let time = parts.next().unwrap_or_default();
+
+    let month = track_assert_some!(
+        MONTHS.iter().position(|m| *m == month),
+        ErrorKind::Other,
+        "Unknown month: {:?}",
+        month
+    ) as i64
+        + 1;
+    let day = track!(parse_numbers(day, '/'))?;
+    let year = track!(parse_numbers(year, '/'))?;
+    let time = track!(parse_numbers(time, ':'))?;
+    track_assert_eq!(time.len(), 3, ErrorKind::Other; s);
+    let offset = track!(parse_utc_offset(offset))?;
+    Ok(to_unix_time(year[0], month, day[0], time[0], time[1], time[2], offset) as f64)
+}
+
+/// Parses `+09:00` or `-0700` into seconds.
+fn parse_utc_offset(s: &str) -> Result<i64> {
+    let sign = match s.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => track_panic!(ErrorKind::Other, "Invalid UTC offset: {:?}", s),
+    };
+    let digits = s[1..].replace(':', "");
+    track_assert!(
+        digits.len() == 4 && digits.is_ascii(),
+        ErrorKind::Other,
+        "Invalid UTC offset: {:?}",
+        s
+    );
+    let hours = track!(parse_numbers(&digits[..2], ':'))?[0];
+    let minutes = track!(parse_numbers(&digits[2..], ':'))?[0];
+    Ok(sign * (hours * 3600 + minutes * 60))
+}
+
+fn parse_numbers(s: &str, delimiter: char) -> Result<Vec<i64>> {
+    s.split(delimiter)
+        .map(|n| track!(n.parse().map_err(|e| Error::from(ErrorKind::Other.cause(e))); n))
+        .collect()
+}
+
+fn to_unix_time(
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+    offset: i64,
+) -> i64 {
+    // Days from civil (https://howardhinnant.github.io/date_algorithms.html).
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    days * 86400 + hour * 3600 + minute * 60 + second - offset
+}
+
+#[derive(Debug, Deserialize)]
+struct Har {
+    log: HarLog,
+}
+
+#[derive(Debug, Deserialize)]
+struct HarLog {
+    entries: Vec<HarEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HarEntry {
+    started_date_time: String,
+    request: HarRequest,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HarRequest {
+    method: String,
+    url: String,
+    #[serde(default)]
+    headers: Vec<HarHeader>,
+    #[serde(default)]
+    post_data: Option<HarPostData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HarHeader {
+    name: String,
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct HarPostData {
+    #[serde(default)]
+    text: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_timestamps_works() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z").unwrap(), 0.0);
+        assert_eq!(
+            parse_rfc3339("2000-10-10T13:55:36.5-07:00").unwrap(),
+            971_211_336.5
+        );
+        assert_eq!(
+            parse_clf_time("10/Oct/2000:13:55:36 -0700").unwrap(),
+            971_211_336.0
+        );
+        assert!(parse_rfc3339("2024-01-02T03:04:0\u{e9}").is_err());
+        assert!(parse_rfc3339("2024-01-0\u{e9}T03:04:05Z").is_err());
+    }
+
+    #[test]
+    fn is_replayable_header_works() {
+        assert!(is_replayable_header(
+            "User-Agent",
+            "Mozilla/5.0 (X11; Linux)"
+        ));
+        assert!(!is_replayable_header("Host", "example.com"));
+        assert!(!is_replayable_header(":authority", "example.com"));
+        assert!(!is_replayable_header("X-Foo", "a\r\nb"));
+    }
+
+    #[test]
+    fn read_har_works() {
+        let har = r#"{"log": {"entries": [
+            {"startedDateTime": "2024-01-01T00:00:00Z", "request": {
+              "method": "GET", "url": "http://localhost/a", "headers": [
+                {"name": "Accept", "value": "text/html"},
+                {"name": "accept", "value": "*/*"},
+                {"name": "Cookie", "value": "a=1"},
+                {"name": "Cookie", "value": "b=2"}]}},
+            {"startedDateTime": "yesterday", "request": {"method": "GET", "url": "http://localhost/b"}},
+            {"startedDateTime": "2024-01-01T00:00:01Z", "request": {"method": "GET", "url": "ftp://localhost/c"}},
+            {"startedDateTime": "2024-01-01T00:00:02Z", "request": {"method": "POST", "url": "http://localhost/d"}}
+        ]}}"#;
+        let requests = read_har(har.as_bytes(), None).unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].headers["Accept"], "text/html, */*");
+        assert_eq!(requests[0].headers["Cookie"], "a=1; b=2");
+        assert_eq!(requests[0].headers.len(), 2);
+        assert_eq!(requests[1].url.as_str(), "http://localhost/d");
+    }
+}
@@ -1,16 +1,21 @@
+use crate::expect::Expect;
+use crate::http::{self, ConnectionPool};
 use crate::run::Seconds;
+use crate::tls::TlsConnector;
 use crate::{Error, ErrorKind, Result};
-use fibers_http_client::connection::ConnectionPoolHandle;
-use fibers_http_client::Client;
-use futures::Future;
-use httpcodec::Response;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
 use std::net::{SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
 use std::time::Duration;
 use trackable::error::ErrorKindExt;
 use url::Url;
 
+pub type ResponseFuture = http::ResponseFuture;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Request {
     pub method: Method,
@@ -18,53 +23,59 @@ pub struct Request {
     pub content: Option<Content>,
     pub timeout: Option<Seconds>,
     pub start_time: Option<Seconds>,
-    // thread, time, header
+
+    /// The header fields, which are checked by `collect_headers` when deserialized.
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        deserialize_with = "deserialize_headers"
+    )]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect: Option<Expect>,
+
+    /// A label copied into the results of the request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// Labels copied into the results of the request (see `summary --group-by tag`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
 }
 impl Request {
     pub fn addr(&self) -> Result<SocketAddr> {
-        // TODO: check scheme
-        let host = track!(self.url.host_str().ok_or_else(|| ErrorKind::Other.error()))?;
-        let port = self.url.port_or_known_default().expect("Never fails");
-        let mut addrs = track!(
-            (host, port).to_socket_addrs().map_err(Error::from),
-            "{}:{}",
-            host,
-            port
-        )?;
-        let addr = track_assert_some!(addrs.next(), ErrorKind::Other);
-        Ok(addr)
+        track!(resolve(&self.url))
     }
 
+    /// Sends the request over a pooled connection.
+    ///
+    /// If `tls` is `None`, HTTPS requests use `TlsConnector::shared_default`.
+    /// The DNS, connect and TLS phases of a request sent over a reused connection are zero.
     pub fn call(
         &self,
-        client: &mut Client<ConnectionPoolHandle>,
+        pool: &ConnectionPool,
+        tls: Option<&TlsConnector>,
         timeout: Option<Duration>,
-    ) -> Box<dyn Future<Item = Response<Vec<u8>>, Error = Error> + Send + 'static> {
-        let mut request = client.request(&self.url);
-        if let Some(timeout) = timeout {
-            request = request.timeout(timeout);
+    ) -> ResponseFuture {
+        match track!(pool.send(self, tls, timeout)) {
+            Err(e) => Box::new(futures::failed(e)),
+            Ok(future) => future,
         }
-        match self.method {
-            Method::Get => Box::new(request.get().map_err(Error::from)),
-            Method::Head => Box::new(
-                request
-                    .head()
-                    .map(|res| res.map_body(|_| Vec::new()))
-                    .map_err(Error::from),
-            ),
-            Method::Delete => Box::new(request.delete().map_err(Error::from)),
-            Method::Post => {
-                let content = self.content.as_ref().map_or(Vec::new(), |c| c.to_bytes());
-                Box::new(request.post(content).map_err(Error::from))
-            }
-            Method::Put => {
-                let content = self.content.as_ref().map_or(Vec::new(), |c| c.to_bytes());
-                Box::new(request.put(content).map_err(Error::from))
-            }
+    }
+
+    /// Sends the request over a dedicated connection (i.e., without using the connection pool).
+    pub fn call_oneshot(
+        &self,
+        tls: Option<&TlsConnector>,
+        timeout: Option<Duration>,
+    ) -> ResponseFuture {
+        match track!(http::send(self, tls, timeout)) {
+            Err(e) => Box::new(futures::failed(e)),
+            Ok(future) => future,
         }
     }
 
-    pub fn path(&self) -> Cow<str> {
+    pub fn path(&self) -> Cow<'_, str> {
         if self.url.query().is_none() && self.url.fragment().is_none() {
             Cow::Borrowed(self.url.path())
         } else {
@@ -82,14 +93,151 @@ impl Request {
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+/// Resolves the address of the host of the given URL (which blocks the calling thread).
+pub(crate) fn resolve(url: &Url) -> Result<SocketAddr> {
+    let scheme = url.scheme();
+    track_assert!(
+        scheme == "http" || scheme == "https",
+        ErrorKind::Other,
+        "Unsupported scheme: {}",
+        scheme
+    );
+    let host = track!(url.host_str().ok_or_else(|| ErrorKind::Other.error()))?;
+    let port = url.port_or_known_default().expect("Never fails");
+    let mut addrs = track!(
+        (host, port)
+            .to_socket_addrs()
+            .map_err(|e| Error::from(ErrorKind::Dns.cause(e))),
+        "{}:{}",
+        host,
+        port
+    )?;
+    let addr = track_assert_some!(addrs.next(), ErrorKind::Dns);
+    Ok(addr)
+}
+
+/// Checks that a header field can be sent as is.
+///
+/// The name must be a token and the value must not contain control characters other than HTAB
+/// (in particular, CR and LF).
+pub fn validate_header(name: &str, value: &str) -> Result<()> {
+    track_assert!(
+        !name.is_empty() && name.bytes().all(is_tchar),
+        ErrorKind::InvalidInput,
+        "Invalid header name: {:?}",
+        name
+    );
+    track_assert!(
+        value.bytes().all(|b| b == b'\t' || !b.is_ascii_control()),
+        ErrorKind::InvalidInput,
+        "Control characters in the value of the header {:?}: {:?}",
+        name,
+        value
+    );
+    Ok(())
+}
+
+fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+/// Collects header fields checked by `validate_header`.
+///
+/// Since header names are case-insensitive, names that differ only in case (e.g., `Host` and `host`)
+/// are rejected rather than sent twice.
+pub fn collect_headers<I>(headers: I) -> Result<BTreeMap<String, String>>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut collected = BTreeMap::new();
+    for (name, value) in headers {
+        track!(validate_header(&name, &value))?;
+        track_assert!(
+            !collected
+                .keys()
+                .any(|k: &String| k.eq_ignore_ascii_case(&name)),
+            ErrorKind::InvalidInput,
+            "Duplicate header: {:?}",
+            name
+        );
+        collected.insert(name, value);
+    }
+    Ok(collected)
+}
+
+pub(crate) fn deserialize_headers<'de, D>(
+    deserializer: D,
+) -> std::result::Result<BTreeMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let headers = BTreeMap::<String, String>::deserialize(deserializer)?;
+    track!(collect_headers(headers)).map_err(serde::de::Error::custom)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
 pub enum Method {
     Put,
     Post,
     Get,
     Head,
     Delete,
+    Patch,
+    Options,
+    Other(String),
+}
+impl Method {
+    pub fn as_str(&self) -> &str {
+        match *self {
+            Method::Put => "PUT",
+            Method::Post => "POST",
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+            Method::Other(ref method) => method,
+        }
+    }
+}
+impl TryFrom<String> for Method {
+    type Error = Error;
+
+    /// Makes a `Method` from a token, matching the standard methods case-insensitively (e.g., `get`).
+    fn try_from(f: String) -> Result<Self> {
+        track_assert!(!f.is_empty(), ErrorKind::Other, "Empty method");
+        track!(httpcodec::Method::new(&f).map_err(Error::from); f)?;
+        Ok(match f.to_ascii_uppercase().as_str() {
+            "PUT" => Method::Put,
+            "POST" => Method::Post,
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "DELETE" => Method::Delete,
+            "PATCH" => Method::Patch,
+            "OPTIONS" => Method::Options,
+            _ => Method::Other(f),
+        })
+    }
+}
+impl From<Method> for String {
+    fn from(f: Method) -> Self {
+        match f {
+            Method::Other(method) => method,
+            _ => f.as_str().to_owned(),
+        }
+    }
+}
+impl FromStr for Method {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        track!(Method::try_from(s.to_owned()))
+    }
+}
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -112,3 +260,50 @@ impl Content {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn method_works() {
+        assert_eq!("get".parse::<Method>().unwrap(), Method::Get);
+        assert_eq!("Patch".parse::<Method>().unwrap(), Method::Patch);
+        assert_eq!(
+            "PURGE".parse::<Method>().unwrap(),
+            Method::Other("PURGE".to_owned())
+        );
+        assert!("GET /".parse::<Method>().is_err());
+        assert!(serde_json::from_str::<Method>(r#""options""#).is_ok());
+        assert!(serde_json::from_str::<Method>(r#""""#).is_err());
+    }
+
+    #[test]
+    fn validate_header_works() {
+        assert!(validate_header("Authorization", "Bearer xyz").is_ok());
+        assert!(validate_header("X-Foo", "a\tb").is_ok());
+        assert!(validate_header("X-Foo", "a\r\nX-Bar: b").is_err());
+        assert!(validate_header("X Foo", "a").is_err());
+        assert!(validate_header("", "a").is_err());
+
+        let request = r#"{"method": "GET", "url": "http://localhost/", "content": null,
+                          "timeout": null, "start_time": null, "headers": {"X-Foo": "a\nb"}}"#;
+        assert!(serde_json::from_str::<Request>(request).is_err());
+    }
+
+    #[test]
+    fn header_names_are_compared_case_insensitively() {
+        let request = r#"{"method": "GET", "url": "http://localhost/", "content": null,
+                          "timeout": null, "start_time": null,
+                          "headers": {"Host": "a.example", "host": "b.example"}}"#;
+        assert!(serde_json::from_str::<Request>(request).is_err());
+
+        let e = collect_headers(vec![
+            ("X-Foo".to_owned(), "a".to_owned()),
+            ("x-foo".to_owned(), "b".to_owned()),
+        ])
+        .unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        assert!(collect_headers(vec![("X-Foo".to_owned(), "a".to_owned())]).is_ok());
+    }
+}
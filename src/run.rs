@@ -1,19 +1,29 @@
-use crate::request::Request;
+use crate::abort::{AbortChecker, AbortConditions};
+use crate::expect::{AssertionFailure, Expect};
+use crate::flow::{extract_values, Extractor, FlowGenerator, Session};
+use crate::histogram::IntervalLogRecorder;
+use crate::http::ConnectionPool;
+use crate::profile::{Profile, ProfileKind};
+use crate::progress::{ProgressFormat, ProgressReporter};
+use crate::request::{Request, ResponseFuture};
+use crate::tls::TlsConnector;
 use crate::{Error, ErrorKind, Result};
 use fibers::sync::mpsc;
 use fibers::time::timer;
 use fibers::Spawn;
-use fibers_http_client::connection::{ConnectionPool, ConnectionPoolBuilder, ConnectionPoolHandle};
-use fibers_http_client::Client;
 use futures::{Async, Future, Poll, Stream};
-use httpcodec::Response as HttpResponse;
 use serde::{Deserialize, Serialize};
 use serdeconv;
-use std::collections::BinaryHeap;
-use std::io::Read;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::fmt;
+use std::io::{BufRead, BufWriter, Read, Write};
+use std::iter::Peekable;
 use std::mem;
+use std::str::FromStr;
+use std::sync::atomic::{self, AtomicBool, AtomicUsize};
 use std::sync::{Arc, Mutex};
-use std::time::{self, Duration};
+use std::time::{self, Duration, SystemTime, UNIX_EPOCH};
+use trackable::error::ErrorKindExt;
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Seconds(pub f64);
@@ -43,6 +53,32 @@ impl From<Duration> for Seconds {
         Seconds(f.as_secs() as f64 + (f64::from(f.subsec_nanos()) / 1_000_000_000.0))
     }
 }
+impl FromStr for Seconds {
+    type Err = Error;
+
+    /// Parses a duration such as `"1.5s"` (the unit is one of `ms`, `s` (default), `m` and `h`).
+    fn from_str(s: &str) -> Result<Self> {
+        let i = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(s.len());
+        let (value, unit) = s.split_at(i);
+        let value =
+            track!(f64::from_str(value).map_err(|e| Error::from(ErrorKind::Other.cause(e))); s)?;
+        let scale = match unit {
+            "ms" => 0.001,
+            "" | "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            _ => track_panic!(ErrorKind::Other, "Unknown duration unit: {:?}", s),
+        };
+        let seconds = value * scale;
+        track_assert!(seconds.is_finite(), ErrorKind::Other; s);
+        Ok(Seconds(seconds))
+    }
+}
+
+/// Number of requests issued by a run bounded by neither a count nor time.
+pub const DEFAULT_REQUEST_COUNT: usize = 10;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -52,37 +88,227 @@ pub enum RequestResult {
         seq_no: usize,
         end_time: Seconds,
         elapsed: Seconds,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        scheduled_start_time: Option<Seconds>,
+        #[serde(flatten)]
+        labels: Labels,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        phases: Option<Phases>,
         response: Response,
     },
     Error {
         seq_no: usize,
         end_time: Seconds,
         elapsed: Seconds,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        scheduled_start_time: Option<Seconds>,
+        #[serde(flatten)]
+        labels: Labels,
         error: Error,
     },
+
+    /// A response was received but it violated the `expect` rules of the request.
+    #[serde(rename = "assertion_failed")]
+    AssertionFailed {
+        seq_no: usize,
+        end_time: Seconds,
+        elapsed: Seconds,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        scheduled_start_time: Option<Seconds>,
+        #[serde(flatten)]
+        labels: Labels,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        phases: Option<Phases>,
+        response: Response,
+        failure: AssertionFailure,
+    },
 }
 impl RequestResult {
     pub fn is_ok(&self) -> bool {
         matches!(self, RequestResult::Ok { .. })
     }
+    pub fn is_assertion_failed(&self) -> bool {
+        matches!(self, RequestResult::AssertionFailed { .. })
+    }
     pub fn seq_no(&self) -> usize {
         match *self {
-            RequestResult::Ok { seq_no, .. } | RequestResult::Error { seq_no, .. } => seq_no,
+            RequestResult::Ok { seq_no, .. }
+            | RequestResult::Error { seq_no, .. }
+            | RequestResult::AssertionFailed { seq_no, .. } => seq_no,
         }
     }
     pub fn elapsed(&self) -> Seconds {
         match *self {
-            RequestResult::Ok { elapsed, .. } | RequestResult::Error { elapsed, .. } => elapsed,
+            RequestResult::Ok { elapsed, .. }
+            | RequestResult::Error { elapsed, .. }
+            | RequestResult::AssertionFailed { elapsed, .. } => elapsed,
         }
     }
     pub fn start_time(&self) -> Seconds {
         Seconds(self.end_time().0 - self.elapsed().0)
     }
+    pub fn scheduled_start_time(&self) -> Option<Seconds> {
+        match *self {
+            RequestResult::Ok {
+                scheduled_start_time,
+                ..
+            }
+            | RequestResult::Error {
+                scheduled_start_time,
+                ..
+            }
+            | RequestResult::AssertionFailed {
+                scheduled_start_time,
+                ..
+            } => scheduled_start_time,
+        }
+    }
+
+    /// Returns the latency measured from the scheduled start time of the request (if any).
+    ///
+    /// Unlike `elapsed`, this includes the time the request waited for a free client
+    /// (i.e., it is corrected for coordinated omission).
+    pub fn corrected_elapsed(&self) -> Seconds {
+        match self.scheduled_start_time() {
+            Some(t) if t < self.start_time() => Seconds(self.end_time().0 - t.0),
+            _ => self.elapsed(),
+        }
+    }
     pub fn end_time(&self) -> Seconds {
         match *self {
-            RequestResult::Ok { end_time, .. } | RequestResult::Error { end_time, .. } => end_time,
+            RequestResult::Ok { end_time, .. }
+            | RequestResult::Error { end_time, .. }
+            | RequestResult::AssertionFailed { end_time, .. } => end_time,
+        }
+    }
+    pub fn phases(&self) -> Option<&Phases> {
+        match *self {
+            RequestResult::Ok { ref phases, .. }
+            | RequestResult::AssertionFailed { ref phases, .. } => phases.as_ref(),
+            RequestResult::Error { .. } => None,
+        }
+    }
+    pub fn labels(&self) -> &Labels {
+        match *self {
+            RequestResult::Ok { ref labels, .. }
+            | RequestResult::Error { ref labels, .. }
+            | RequestResult::AssertionFailed { ref labels, .. } => labels,
+        }
+    }
+    pub(crate) fn labels_mut(&mut self) -> &mut Labels {
+        match self {
+            RequestResult::Ok { labels, .. }
+            | RequestResult::Error { labels, .. }
+            | RequestResult::AssertionFailed { labels, .. } => labels,
+        }
+    }
+
+    /// Renumbers the result and shifts its times by `offset`.
+    pub(crate) fn rebase(&mut self, new_seq_no: usize, offset: Seconds) {
+        match self {
+            RequestResult::Ok {
+                seq_no,
+                end_time,
+                scheduled_start_time,
+                ..
+            }
+            | RequestResult::Error {
+                seq_no,
+                end_time,
+                scheduled_start_time,
+                ..
+            }
+            | RequestResult::AssertionFailed {
+                seq_no,
+                end_time,
+                scheduled_start_time,
+                ..
+            } => {
+                *seq_no = new_seq_no;
+                end_time.0 += offset.0;
+                if let Some(t) = scheduled_start_time {
+                    t.0 += offset.0;
+                }
+            }
         }
     }
+
+    /// Returns the name of the load profile stage in which the request was started.
+    pub fn stage(&self) -> Option<&str> {
+        self.labels().stage.as_deref()
+    }
+
+    /// Returns the label of the request.
+    pub fn name(&self) -> Option<&str> {
+        self.labels().name.as_deref()
+    }
+
+    /// Returns the names of the flow and its step which issued the request.
+    pub fn flow_step(&self) -> Option<(&str, &str)> {
+        let labels = self.labels();
+        labels.flow.as_deref().zip(labels.step.as_deref())
+    }
+    pub fn error_kind(&self) -> Option<ErrorKind> {
+        match *self {
+            RequestResult::Error { ref error, .. } => Some(*error.kind()),
+            _ => None,
+        }
+    }
+    pub fn response(&self) -> Option<&Response> {
+        match *self {
+            RequestResult::Ok { ref response, .. }
+            | RequestResult::AssertionFailed { ref response, .. } => Some(response),
+            RequestResult::Error { .. } => None,
+        }
+    }
+}
+
+/// Durations of the phases of a request.
+///
+/// The `dns` and `connect` (and `tls`) phases are zero if the request was sent over a reused connection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Phases {
+    pub dns: Seconds,
+    pub connect: Seconds,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<Seconds>,
+
+    /// Time from the start of sending the request to the first byte of the response.
+    pub ttfb: Seconds,
+
+    /// Time from the first byte to the last byte of the response.
+    pub transfer: Seconds,
+}
+
+/// Attributes of the request of a result, which are used to break the results down.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Labels {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+
+    /// See `Request::name`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// See `Request::tags`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    /// The load profile stage in which the request was started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stage: Option<String>,
+
+    /// The flow and its step which issued the request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flow: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<String>,
+
+    /// The result file from which the result was merged (see `merge`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -104,30 +330,85 @@ impl PartialEq for QueueItem {
 impl Eq for QueueItem {}
 impl PartialOrd for QueueItem {
     fn partial_cmp(&self, other: &Self) -> Option<::std::cmp::Ordering> {
-        (other.request.start_time, other.seq_no)
-            .partial_cmp(&(self.request.start_time, self.seq_no))
+        Some(self.cmp(other))
     }
 }
 impl Ord for QueueItem {
     fn cmp(&self, other: &Self) -> ::std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+        (other.request.start_time, other.seq_no).cmp(&(self.request.start_time, self.seq_no))
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct RequestQueue {
-    requests: Arc<Mutex<BinaryHeap<QueueItem>>>,
+    requests: Arc<Mutex<RequestQueueInner>>,
 }
 impl RequestQueue {
     pub fn new(requests: Vec<Request>) -> Self {
-        let requests = Arc::new(Mutex::new(
-            requests
-                .into_iter()
-                .enumerate()
-                .map(|(seq_no, request)| QueueItem { seq_no, request })
-                .collect(),
-        ));
-        RequestQueue { requests }
+        let heap = requests
+            .into_iter()
+            .enumerate()
+            .map(|(seq_no, request)| QueueItem { seq_no, request })
+            .collect::<BinaryHeap<_>>();
+        let inner = RequestQueueInner {
+            next_seq_no: heap.len(),
+            total: Some(heap.len()),
+            heap,
+            generator: None,
+            error: None,
+            sessions: None,
+        };
+        RequestQueue {
+            requests: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    /// Makes a queue that lazily pulls requests from the given iterator.
+    ///
+    /// The start times of the generated requests (if any) must be monotonically increasing.
+    pub fn with_generator<I>(generator: I) -> Self
+    where
+        I: Iterator<Item = Request> + Send + 'static,
+    {
+        Self::with_fallible_generator(generator.map(Ok))
+    }
+
+    /// Makes a queue that lazily pulls requests from the given iterator which may fail.
+    ///
+    /// Once the iterator yields an error, every subsequent `pop` fails with it (i.e., the run is stopped).
+    pub fn with_fallible_generator<I>(generator: I) -> Self
+    where
+        I: Iterator<Item = Result<Request>> + Send + 'static,
+    {
+        // Unbounded generators (e.g., `0..usize::MAX`) are regarded as having an unknown length.
+        let total = generator.size_hint().1.filter(|&n| n != usize::MAX);
+        let inner = RequestQueueInner {
+            heap: BinaryHeap::new(),
+            total,
+            generator: Some((Box::new(generator) as Box<dyn Iterator<Item = _> + Send>).peekable()),
+            error: None,
+            sessions: None,
+            next_seq_no: 0,
+        };
+        RequestQueue {
+            requests: Arc::new(Mutex::new(inner)),
+        }
+    }
+    /// Makes a queue of flow sessions.
+    ///
+    /// Each session is run by a single client, which issues the requests of its steps in order.
+    pub fn with_flows(sessions: FlowGenerator) -> Self {
+        let inner = RequestQueueInner {
+            heap: BinaryHeap::new(),
+            total: sessions.request_count(),
+            generator: None,
+            error: None,
+            sessions: Some(sessions),
+            next_seq_no: 0,
+        };
+        RequestQueue {
+            requests: Arc::new(Mutex::new(inner)),
+        }
     }
     pub fn read_from<R: Read>(reader: R) -> Result<Self> {
         let requests = track!(serdeconv::from_json_reader(reader))?;
@@ -135,53 +416,202 @@ impl RequestQueue {
     }
     pub fn push(&self, seq_no: usize, request: Request) -> Result<()> {
         let mut requests = track!(self.requests.lock().map_err(Error::from))?;
-        requests.push(QueueItem { seq_no, request });
+        requests.heap.push(QueueItem { seq_no, request });
         Ok(())
     }
+
+    /// Excludes `n` requests that will never be issued (e.g., the remaining steps of an aborted
+    /// flow session) from the total.
+    pub fn skip(&self, n: usize) -> Result<()> {
+        let mut requests = track!(self.requests.lock().map_err(Error::from))?;
+        if let Some(total) = requests.total.as_mut() {
+            *total = total.saturating_sub(n);
+        }
+        Ok(())
+    }
+
+    /// Returns the total number of requests (including the ones already popped) if it is known.
+    pub fn total(&self) -> Result<Option<usize>> {
+        let requests = track!(self.requests.lock().map_err(Error::from))?;
+        Ok(requests.total)
+    }
     pub fn pop(&self) -> Result<Option<(usize, Request)>> {
         let mut requests = track!(self.requests.lock().map_err(Error::from))?;
-        Ok(requests.pop().map(|x| (x.seq_no, x.request)))
+        track!(requests.generate())?;
+        Ok(requests.heap.pop().map(|x| (x.seq_no, x.request)))
+    }
+    pub fn pop_session(&self) -> Result<Option<Session>> {
+        let mut requests = track!(self.requests.lock().map_err(Error::from))?;
+        Ok(requests.sessions.as_mut().and_then(|s| s.next()))
+    }
+
+    /// Allocates a sequence number for a request that is not in the queue (e.g., a flow step).
+    pub fn next_seq_no(&self) -> Result<usize> {
+        let mut requests = track!(self.requests.lock().map_err(Error::from))?;
+        let seq_no = requests.next_seq_no;
+        requests.next_seq_no += 1;
+        Ok(seq_no)
+    }
+}
+
+struct RequestQueueInner {
+    heap: BinaryHeap<QueueItem>,
+    generator: Option<Peekable<Box<dyn Iterator<Item = Result<Request>> + Send>>>,
+
+    /// The error yielded by the generator (if any).
+    error: Option<Error>,
+    sessions: Option<FlowGenerator>,
+    next_seq_no: usize,
+    total: Option<usize>,
+}
+impl RequestQueueInner {
+    /// Moves the next request of the generator into the heap if it has to be issued before
+    /// the head of the heap (i.e., the requests pushed back by the clients).
+    fn generate(&mut self) -> Result<()> {
+        if let Some(e) = &self.error {
+            return Err(track!(e.clone()));
+        }
+        let generator = match self.generator.as_mut() {
+            Some(g) => g,
+            None => return Ok(()),
+        };
+        let next_start_time = match generator.peek() {
+            Some(Ok(request)) => request.start_time,
+            Some(Err(_)) | None => {
+                let result = generator.next().transpose();
+                self.generator = None;
+                return result.map(|_| ()).map_err(|e| {
+                    self.error = Some(e.clone());
+                    track!(e)
+                });
+            }
+        };
+        if self
+            .heap
+            .peek()
+            .is_some_and(|head| head.request.start_time <= next_start_time)
+        {
+            return Ok(());
+        }
+        let request = generator.next().expect("Never fails").expect("Never fails");
+        let seq_no = self.next_seq_no;
+        self.next_seq_no += 1;
+        self.heap.push(QueueItem { seq_no, request });
+        Ok(())
+    }
+}
+impl fmt::Debug for RequestQueueInner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RequestQueueInner")
+            .field("heap", &self.heap)
+            .field("generator", &self.generator.as_ref().map(|_| "..."))
+            .field("error", &self.error)
+            .field("sessions", &self.sessions)
+            .field("next_seq_no", &self.next_seq_no)
+            .finish()
     }
 }
 
 pub struct RunRequest {
-    future: Box<dyn Future<Item = HttpResponse<Vec<u8>>, Error = Error> + Send + 'static>,
+    future: ResponseFuture,
+    expect: Option<Expect>,
+    extract: BTreeMap<String, Extractor>,
 }
 impl RunRequest {
-    pub fn new(request: &Request, client: &mut Client<ConnectionPoolHandle>) -> Result<Self> {
-        let future = request.call(client, request.timeout.map(|t| t.to_duration()));
-        Ok(RunRequest { future })
+    /// Makes a new `RunRequest` instance.
+    ///
+    /// If `pool` is `None`, the request is sent without using the connection pool.
+    pub fn new(
+        request: &Request,
+        pool: Option<&ConnectionPool>,
+        tls: Option<&TlsConnector>,
+    ) -> Result<Self> {
+        let timeout = request.timeout.map(|t| t.to_duration());
+        let future = if let Some(pool) = pool {
+            request.call(pool, tls, timeout)
+        } else {
+            request.call_oneshot(tls, timeout)
+        };
+        Ok(RunRequest {
+            future,
+            expect: request.expect.clone(),
+            extract: BTreeMap::new(),
+        })
+    }
+
+    /// Sets the values to extract from the response.
+    ///
+    /// A response lacking any of them is regarded as an assertion failure.
+    pub fn extract(&mut self, extractors: BTreeMap<String, Extractor>) {
+        self.extract = extractors;
     }
 }
 impl Future for RunRequest {
-    type Item = Response;
+    /// The last element is the extracted values.
+    type Item = (
+        Response,
+        Phases,
+        Option<AssertionFailure>,
+        BTreeMap<String, String>,
+    );
     type Error = Error;
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        if let Async::Ready(response) = track!(self.future.poll())? {
+        if let Async::Ready((response, phases)) = track!(self.future.poll())? {
+            let mut failure = self
+                .expect
+                .as_ref()
+                .and_then(|expect| expect.check(&response).err());
+            let mut values = BTreeMap::new();
+            if failure.is_none() {
+                match extract_values(&self.extract, &response) {
+                    Ok(v) => values = v,
+                    Err(f) => failure = Some(f),
+                }
+            }
             let response = Response {
                 status: response.status_code().as_u16(),
                 content_length: response.body().len() as u64,
             };
-            Ok(Async::Ready(response))
+            Ok(Async::Ready((response, phases, failure, values)))
         } else {
             Ok(Async::NotReady)
         }
     }
 }
 
+/// Settings shared by all clients of a run.
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    pub keep_alive: bool,
+    pub tls: Option<TlsConnector>,
+    pub deadline: Option<Duration>,
+    pub stop: StopHandle,
+    pub profile: Option<Arc<Profile>>,
+}
+
 pub struct ClientFiber {
-    pool: ConnectionPoolHandle,
+    pool: ConnectionPool,
+    options: ClientOptions,
     requests: RequestQueue,
     response_tx: mpsc::Sender<RequestResult>,
     last_seq_no: usize, // TODO
+    last_scheduled_start_time: Option<Seconds>,
+    last_labels: Labels,
+    session: Option<Session>,
+    index: usize,
     start_time: time::Instant,
     bench_start: time::Instant,
     next_start: Option<timer::Timeout>,
     future: Option<RunRequest>,
 }
 impl ClientFiber {
+    /// Makes a new `ClientFiber` instance.
+    ///
+    /// `index` is used to decide whether the client is active under a concurrency profile.
     pub fn new(
-        pool: ConnectionPoolHandle,
+        pool: ConnectionPool,
+        options: ClientOptions,
+        index: usize,
         bench_start: time::Instant,
         requests: RequestQueue,
         response_tx: mpsc::Sender<RequestResult>,
@@ -189,7 +619,12 @@ impl ClientFiber {
         log::info!("Starts a client");
         ClientFiber {
             pool,
+            options,
             last_seq_no: 0,
+            last_scheduled_start_time: None,
+            last_labels: Labels::default(),
+            session: None,
+            index,
             start_time: time::Instant::now(),
             bench_start,
             requests,
@@ -199,6 +634,83 @@ impl ClientFiber {
         }
     }
 }
+impl ClientFiber {
+    fn is_active(&self) -> bool {
+        match self.options.profile.as_ref() {
+            Some(p) if p.kind == ProfileKind::Concurrency => {
+                let concurrency = p.target_at(self.bench_start.elapsed().into());
+                (self.index as f64) < concurrency.ceil()
+            }
+            _ => true,
+        }
+    }
+
+    fn stage_at(&self, start_time: Option<Seconds>) -> Option<String> {
+        self.options.profile.as_ref().map(|p| {
+            let t = start_time.unwrap_or_else(|| self.bench_start.elapsed().into());
+            p.stage_name_at(t)
+        })
+    }
+
+    /// Gives up the rest of the current flow session since its current step has failed.
+    fn abort_session(&mut self) -> Result<()> {
+        if let Some(session) = self.session.take() {
+            log::info!("Aborts the flow {:?}", session.flow_name());
+            track!(self
+                .requests
+                .skip(session.remaining_steps().saturating_sub(1)))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the next request, continuing the current flow session (if any) first.
+    ///
+    /// If the request of a step cannot be made (e.g., an extracted value is not a valid header value),
+    /// an error result is recorded for the step and its session is aborted.
+    fn next_request(&mut self) -> Result<Option<(usize, Request)>> {
+        loop {
+            if let Some(session) = self.session.as_ref() {
+                match session.request() {
+                    Ok(request) => {
+                        let seq_no = track!(self.requests.next_seq_no())?;
+                        return Ok(Some((seq_no, request)));
+                    }
+                    Err(e) => {
+                        log::warn!("Aborts the flow {:?}: {}", session.flow_name(), e);
+                        let step = session.step().expect("Never fails");
+                        let labels = Labels {
+                            method: Some(step.request.method.to_string()),
+                            url: None,
+                            name: step.request.name.clone(),
+                            tags: step.request.tags.clone(),
+                            stage: self.stage_at(None),
+                            flow: Some(session.flow_name().to_owned()),
+                            step: Some(step.name.clone()),
+                            source: None,
+                        };
+                        let result = RequestResult::Error {
+                            seq_no: track!(self.requests.next_seq_no())?,
+                            end_time: self.bench_start.elapsed().into(),
+                            elapsed: Seconds(0.0),
+                            scheduled_start_time: None,
+                            labels,
+                            error: e,
+                        };
+                        track!(self.response_tx.send(result).map_err(Error::from))?;
+                        track!(self.abort_session())?;
+                    }
+                }
+            }
+            if let Some(x) = track!(self.requests.pop())? {
+                return Ok(Some(x));
+            }
+            match track!(self.requests.pop_session())? {
+                Some(session) => self.session = Some(session),
+                None => return Ok(None),
+            }
+        }
+    }
+}
 impl Future for ClientFiber {
     type Item = ();
     type Error = Error;
@@ -216,6 +728,8 @@ impl Future for ClientFiber {
                         seq_no: self.last_seq_no,
                         end_time: self.bench_start.elapsed().into(),
                         elapsed: self.start_time.elapsed().into(),
+                        scheduled_start_time: self.last_scheduled_start_time,
+                        labels: self.last_labels.clone(),
                         error: e.clone(),
                     };
                     log::info!(
@@ -227,12 +741,36 @@ impl Future for ClientFiber {
                     log::debug!("{}", e);
                     track!(self.response_tx.send(result).map_err(Error::from))?;
                     self.future = None;
+                    track!(self.abort_session())?;
+                }
+                Ok(Async::Ready(Some((response, phases, Some(failure), _)))) => {
+                    let result = RequestResult::AssertionFailed {
+                        seq_no: self.last_seq_no,
+                        end_time: self.bench_start.elapsed().into(),
+                        elapsed: self.start_time.elapsed().into(),
+                        scheduled_start_time: self.last_scheduled_start_time,
+                        labels: self.last_labels.clone(),
+                        phases: Some(phases),
+                        response,
+                        failure,
+                    };
+                    log::info!(
+                        "Assertion failed: seq_no={}, elapsed={}",
+                        result.seq_no(),
+                        result.elapsed().0
+                    );
+                    track!(self.response_tx.send(result).map_err(Error::from))?;
+                    self.future = None;
+                    track!(self.abort_session())?;
                 }
-                Ok(Async::Ready(Some(response))) => {
+                Ok(Async::Ready(Some((response, phases, None, values)))) => {
                     let result = RequestResult::Ok {
                         seq_no: self.last_seq_no,
                         end_time: self.bench_start.elapsed().into(),
                         elapsed: self.start_time.elapsed().into(),
+                        scheduled_start_time: self.last_scheduled_start_time,
+                        labels: self.last_labels.clone(),
+                        phases: Some(phases),
                         response,
                     };
                     log::info!(
@@ -242,12 +780,39 @@ impl Future for ClientFiber {
                     );
                     track!(self.response_tx.send(result).map_err(Error::from))?;
                     self.future = None;
+                    if let Some(session) = self.session.as_mut() {
+                        session.advance(values);
+                        if session.step().is_none() {
+                            self.session = None;
+                        }
+                    }
                 }
                 Ok(Async::Ready(None)) => {
-                    if let Some((seq_no, request)) = track!(self.requests.pop())? {
+                    if self.options.stop.is_stopped() {
+                        log::info!("The run has been stopped");
+                        return Ok(Async::Ready(()));
+                    }
+                    if self
+                        .options
+                        .deadline
+                        .is_some_and(|d| d <= self.bench_start.elapsed())
+                    {
+                        log::info!("The run duration has elapsed");
+                        return Ok(Async::Ready(()));
+                    }
+                    if !self.is_active() {
+                        self.next_start = Some(timer::timeout(PROFILE_CHECK_INTERVAL));
+                        continue;
+                    }
+                    if let Some((seq_no, request)) = track!(self.next_request())? {
                         if let Some(start_time) = request.start_time {
                             let elapsed = self.bench_start.elapsed();
                             let start_time = Duration::from(start_time);
+                            if self.options.deadline.is_some_and(|d| d <= start_time) {
+                                log::info!("The run duration will elapse before the next request");
+                                track!(self.requests.push(seq_no, request))?;
+                                return Ok(Async::Ready(()));
+                            }
                             if elapsed <= start_time {
                                 let wait = start_time - elapsed;
                                 log::info!("Wait: {:?}", wait);
@@ -259,10 +824,30 @@ impl Future for ClientFiber {
 
                         log::info!("New request is started: seq_no={}", seq_no);
                         self.last_seq_no = seq_no;
+                        self.last_scheduled_start_time = request.start_time;
+                        let step = self.session.as_ref().and_then(|s| Some((s, s.step()?)));
+                        self.last_labels = Labels {
+                            method: Some(request.method.to_string()),
+                            url: Some(request.url.to_string()),
+                            name: request.name.clone(),
+                            tags: request.tags.clone(),
+                            stage: self.stage_at(request.start_time),
+                            flow: step.map(|(s, _)| s.flow_name().to_owned()),
+                            step: step.map(|(_, step)| step.name.clone()),
+                            source: None,
+                        };
                         self.start_time = time::Instant::now();
 
-                        let mut client = Client::new(self.pool.clone());
-                        let future = track!(RunRequest::new(&request, &mut client))?;
+                        let pool = if self.options.keep_alive {
+                            Some(&self.pool)
+                        } else {
+                            None
+                        };
+                        let mut future =
+                            track!(RunRequest::new(&request, pool, self.options.tls.as_ref()))?;
+                        if let Some(step) = self.session.as_ref().and_then(|s| s.step()) {
+                            future.extract(step.extract.clone());
+                        }
                         self.future = Some(future);
                     } else {
                         return Ok(Async::Ready(()));
@@ -278,6 +863,12 @@ impl Future for ClientFiber {
 pub struct RunnerBuilder {
     concurrency: usize,
     connection_pool_size: usize,
+    duration: Option<Duration>,
+    tls_connector: Option<TlsConnector>,
+    keep_alive: bool,
+    shutdown_timeout: Duration,
+    profile: Option<Profile>,
+    abort_conditions: AbortConditions,
 }
 impl RunnerBuilder {
     pub fn new() -> Self {
@@ -291,29 +882,133 @@ impl RunnerBuilder {
         self.connection_pool_size = size;
         self
     }
+
+    /// Sets the maximum duration of the run.
+    ///
+    /// Once the duration has elapsed, no new requests are issued and the runner finishes
+    /// after the in-flight requests complete.
+    pub fn duration(&mut self, duration: Duration) -> &mut Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// If `false`, each request is sent over a new connection instead of a pooled one.
+    ///
+    /// The phase timings (DNS, connect, TLS, TTFB, transfer) of each request are recorded in either case,
+    /// but the DNS, connect and TLS phases of the requests sent over reused connections are zero.
+    pub fn keep_alive(&mut self, keep_alive: bool) -> &mut Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Sets how long the runner waits for in-flight requests after it has been stopped.
+    ///
+    /// The default value is 5 seconds.
+    pub fn shutdown_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Sets the load profile of the run.
+    ///
+    /// Each result is tagged with the stage in which its request was started, and
+    /// the run finishes after the last stage (or the duration given by `duration`).
+    /// If the profile is a concurrency profile, it also overrides `concurrency`.
+    /// The requests of rate profiles must be scheduled by the caller (see `Profile::start_times`).
+    pub fn profile(&mut self, profile: Profile) -> &mut Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Sets the conditions that abort the run early.
+    ///
+    /// Once one of them is met, the runner stops in the same way as `StopHandle::stop` and
+    /// the reason is reported in `RunOutput::stopped`.
+    pub fn abort_conditions(&mut self, conditions: AbortConditions) -> &mut Self {
+        self.abort_conditions = conditions;
+        self
+    }
+
+    /// Sets the connector used for HTTPS requests.
+    ///
+    /// If omitted, `TlsConnector::shared_default` is used (and made when the first HTTPS request is sent).
+    pub fn tls_connector(&mut self, connector: TlsConnector) -> &mut Self {
+        self.tls_connector = Some(connector);
+        self
+    }
     pub fn finish<S>(&self, spawner: &S, requests: &RequestQueue) -> Runner
     where
         S: Spawn + Clone + Send + 'static,
     {
         let bench_start = time::Instant::now();
-        let responses = Vec::with_capacity(requests.requests.lock().unwrap().len());
-        let connection_pool = ConnectionPoolBuilder::new()
-            .max_pool_size(self.connection_pool_size)
-            .finish(spawner.clone());
+        let started = Started {
+            unix_time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .into(),
+        };
+        let responses = Vec::new();
+        let connection_pool = ConnectionPool::new(self.connection_pool_size);
+        let stop = StopHandle::default();
+        let mut concurrency = self.concurrency;
+        let mut deadline = self.duration;
+        if let Some(profile) = &self.profile {
+            if profile.kind == ProfileKind::Concurrency {
+                concurrency = profile.max_target().ceil() as usize;
+            }
+            let end = Duration::from(profile.duration());
+            deadline = Some(deadline.map_or(end, |d| d.min(end)));
+        }
+        let options = ClientOptions {
+            keep_alive: self.keep_alive,
+            tls: self.tls_connector.clone(),
+            deadline,
+            stop: stop.clone(),
+            profile: self.profile.clone().map(Arc::new),
+        };
         let (response_tx, response_rx) = mpsc::channel();
-        for _ in 0..self.concurrency {
+        let finished_clients = Arc::new(AtomicUsize::new(0));
+        for index in 0..concurrency {
             let future = ClientFiber::new(
-                connection_pool.handle(),
+                connection_pool.clone(),
+                options.clone(),
+                index,
                 bench_start,
                 requests.clone(),
                 response_tx.clone(),
             );
-            spawner.spawn(future.map_err(|e| panic!("Error: {}", e)));
+            let finished = Arc::clone(&finished_clients);
+            spawner.spawn(future.then(move |result| {
+                match result {
+                    Ok(()) => {
+                        finished.fetch_add(1, atomic::Ordering::SeqCst);
+                    }
+                    Err(e) => log::error!("A client failed: {}", e),
+                }
+                Ok(())
+            }));
         }
         Runner {
             responses,
             response_rx,
-            connection_pool,
+            clients: concurrency,
+            finished_clients,
+            ndjson_writer: None,
+            bench_start,
+            started,
+            stop,
+            stop_reason: None,
+            shutdown_timeout: self.shutdown_timeout,
+            shutdown_deadline: None,
+            stop_check_timer: timer::timeout(STOP_CHECK_INTERVAL),
+            requests: requests.clone(),
+            progress: None,
+            histogram_log: None,
+            abort_checker: if self.abort_conditions.is_empty() {
+                None
+            } else {
+                Some(AbortChecker::new(self.abort_conditions.clone()))
+            },
         }
     }
 }
@@ -322,15 +1017,39 @@ impl Default for RunnerBuilder {
         RunnerBuilder {
             concurrency: 128,
             connection_pool_size: 4096,
+            duration: None,
+            tls_connector: None,
+            keep_alive: true,
+            shutdown_timeout: Duration::from_secs(5),
+            profile: None,
+            abort_conditions: AbortConditions::default(),
         }
     }
 }
 
+const PROFILE_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
 #[derive(Debug)]
 pub struct Runner {
     responses: Vec<RequestResult>,
     response_rx: mpsc::Receiver<RequestResult>,
-    connection_pool: ConnectionPool,
+    clients: usize,
+
+    /// The number of the clients that have finished without errors.
+    finished_clients: Arc<AtomicUsize>,
+    ndjson_writer: Option<NdjsonWriter>,
+    bench_start: time::Instant,
+    started: Started,
+    stop: StopHandle,
+    stop_reason: Option<StopReason>,
+    shutdown_timeout: Duration,
+    shutdown_deadline: Option<timer::Timeout>,
+    stop_check_timer: timer::Timeout,
+    requests: RequestQueue,
+    progress: Option<ProgressReporter>,
+    histogram_log: Option<IntervalLogRecorder>,
+    abort_checker: Option<AbortChecker>,
 }
 impl Runner {
     pub fn new<S>(spawner: &S, requests: &RequestQueue) -> Self
@@ -339,24 +1058,593 @@ impl Runner {
     {
         RunnerBuilder::new().finish(spawner, requests)
     }
+
+    /// Makes the runner write each result to `writer` as a line of JSON as soon as it arrives.
+    ///
+    /// In this mode, the results are not kept in memory and
+    /// the `Vec` returned by the runner is empty.
+    ///
+    /// The `Started` marker is written immediately as the first line.
+    pub fn set_ndjson_writer<W: Write + Send + 'static>(&mut self, writer: W) -> Result<()> {
+        let mut writer = NdjsonWriter(Box::new(BufWriter::new(writer)));
+        track!(serdeconv::to_json_writer(
+            &Marker::Started(&self.started),
+            &mut writer.0
+        ))?;
+        track!(writer.0.write_all(b"\n").map_err(Error::from))?;
+        self.ndjson_writer = Some(writer);
+        Ok(())
+    }
+
+    /// Makes the runner write a progress report to `writer` at every `interval`.
+    pub fn set_progress_writer<W: Write + Send + 'static>(
+        &mut self,
+        writer: W,
+        format: ProgressFormat,
+        interval: Duration,
+    ) {
+        self.progress = Some(ProgressReporter::new(
+            Box::new(writer),
+            format,
+            interval,
+            self.bench_start,
+        ));
+    }
+
+    /// Makes the runner record the latencies into HDR histograms and
+    /// write them to `writer` as an interval log (a pair of histograms per `interval`).
+    pub fn set_histogram_log_writer<W: Write + Send + 'static>(
+        &mut self,
+        writer: W,
+        interval: Duration,
+    ) -> Result<()> {
+        let recorder = track!(IntervalLogRecorder::new(
+            Box::new(BufWriter::new(writer)),
+            interval,
+            self.bench_start,
+        ))?;
+        self.histogram_log = Some(recorder);
+        Ok(())
+    }
+
+    /// Returns a handle for stopping the run.
+    ///
+    /// After being stopped, the runner issues no new requests,
+    /// waits for the in-flight requests (up to the shutdown timeout), and then
+    /// finishes with the results collected so far.
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    fn handle_result(&mut self, result: RequestResult) -> Result<()> {
+        if let Some(progress) = self.progress.as_mut() {
+            progress.record(&result);
+        }
+        if let Some(log) = self.histogram_log.as_mut() {
+            log.record(&result);
+        }
+        if let Some(reason) = self.abort_checker.as_mut().and_then(|c| c.record(&result)) {
+            self.abort(reason);
+        }
+        if let Some(writer) = self.ndjson_writer.as_mut() {
+            track!(serdeconv::to_json_writer(&result, &mut writer.0))?;
+            track!(writer.0.write_all(b"\n").map_err(Error::from))?;
+        } else {
+            self.responses.push(result);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<RunOutput> {
+        let stopped = self.stop_reason.take().map(|reason| Stopped {
+            end_time: self.bench_start.elapsed().into(),
+            reason,
+        });
+        if let (Some(writer), Some(stopped)) = (self.ndjson_writer.as_mut(), stopped.as_ref()) {
+            track!(serdeconv::to_json_writer(
+                &Marker::Stopped(stopped),
+                &mut writer.0
+            ))?;
+            track!(writer.0.write_all(b"\n").map_err(Error::from))?;
+        }
+        track!(self.flush())?;
+        if let Some(progress) = self.progress.as_mut() {
+            let total = track!(self.requests.total())?;
+            track!(progress.report(total))?;
+        }
+        if let Some(log) = self.histogram_log.as_mut() {
+            track!(log.finish())?;
+        }
+
+        let mut results = mem::take(&mut self.responses);
+        results.sort_by_key(|r| r.seq_no());
+        Ok(RunOutput {
+            started: self.started.clone(),
+            results,
+            stopped,
+        })
+    }
+
+    fn abort(&mut self, reason: StopReason) {
+        if self.stop_reason.is_none() {
+            log::warn!("Aborting the run: {}", reason);
+            self.stop_reason = Some(reason);
+        }
+        self.stop.stop();
+    }
+
+    fn poll_stop(&mut self) -> Result<bool> {
+        while let Async::Ready(()) = track!(self.stop_check_timer.poll().map_err(Error::from))? {
+            self.stop_check_timer = timer::timeout(STOP_CHECK_INTERVAL);
+        }
+        let elapsed = self.bench_start.elapsed();
+        if let Some(reason) = self
+            .abort_checker
+            .as_ref()
+            .and_then(|c| c.check_wall_time(elapsed))
+        {
+            // NOTE: The in-flight requests are not waited for.
+            self.abort(reason);
+            return Ok(true);
+        }
+        if self.shutdown_deadline.is_none() && self.stop.is_stopped() {
+            log::info!("The run has been stopped");
+            if self.stop_reason.is_none() {
+                self.stop_reason = Some(StopReason::Interrupted);
+            }
+            self.shutdown_deadline = Some(timer::timeout(self.shutdown_timeout));
+        }
+        if let Some(deadline) = self.shutdown_deadline.as_mut() {
+            if let Async::Ready(()) = track!(deadline.poll().map_err(Error::from))? {
+                log::warn!("Gave up waiting for the in-flight requests");
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(writer) = self.ndjson_writer.as_mut() {
+            track!(writer.0.flush().map_err(Error::from))?;
+        }
+        Ok(())
+    }
 }
 impl Future for Runner {
-    type Item = Vec<RequestResult>;
+    type Item = RunOutput;
     type Error = Error;
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         while let Async::Ready(polled) = self.response_rx.poll().expect("Never fails") {
             if let Some(response) = polled {
-                self.responses.push(response);
-                if self.responses.len() == self.responses.capacity() {
-                    let mut responses = mem::take(&mut self.responses);
-                    responses.sort_by_key(|r| r.seq_no());
-                    return Ok(Async::Ready(responses));
-                }
+                track!(self.handle_result(response))?;
             } else {
-                track_panic!(ErrorKind::Other, "All workers down");
+                // All clients have finished
+                let finished = self.finished_clients.load(atomic::Ordering::SeqCst);
+                track_assert!(
+                    finished == self.clients,
+                    ErrorKind::Other,
+                    "All workers down: {} of {} clients failed",
+                    self.clients - finished,
+                    self.clients
+                );
+                track!(self.poll_stop())?;
+                return Ok(Async::Ready(track!(self.finish())?));
             }
         }
-        track!(self.connection_pool.poll())?;
+        if track!(self.poll_stop())? {
+            return Ok(Async::Ready(track!(self.finish())?));
+        }
+        if let Some(progress) = self.progress.as_mut() {
+            let total = track!(self.requests.total())?;
+            track!(progress.poll(total))?;
+        }
+        if let Some(log) = self.histogram_log.as_mut() {
+            track!(log.poll())?;
+        }
+        track!(self.flush())?;
         Ok(Async::NotReady)
     }
 }
+
+/// The outcome of a run.
+#[derive(Debug)]
+pub struct RunOutput {
+    pub started: Started,
+
+    /// The results sorted by `seq_no`.
+    ///
+    /// This is empty if the runner wrote the results to an NDJSON writer.
+    pub results: Vec<RequestResult>,
+
+    /// `Some(_)` if the run was stopped before all the requests were issued.
+    pub stopped: Option<Stopped>,
+}
+
+/// A handle for stopping a run (see `Runner::stop_handle`).
+#[derive(Debug, Default, Clone)]
+pub struct StopHandle(Arc<AtomicBool>);
+impl StopHandle {
+    pub fn stop(&self) {
+        self.0.store(true, atomic::Ordering::SeqCst);
+    }
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(atomic::Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "reason")]
+pub enum StopReason {
+    /// The run was interrupted by a signal or a `StopHandle`.
+    Interrupted,
+
+    /// The error rate (in percent) of the latest results exceeded `AbortConditions::max_error_rate`.
+    ErrorRate { error_rate: f64, max: f64 },
+
+    /// `AbortConditions::max_consecutive_failures` requests failed in a row.
+    ConsecutiveFailures { count: usize },
+
+    /// The latency percentile of the latest results exceeded `AbortConditions::max_latency`.
+    Latency {
+        percentile: f64,
+        latency: Seconds,
+        max: Seconds,
+    },
+
+    /// The run exceeded `AbortConditions::max_wall_time`.
+    WallTime { max: Seconds },
+}
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Interrupted => write!(f, "Interrupted"),
+            StopReason::ErrorRate { error_rate, max } => {
+                write!(f, "Error rate {:.2}% exceeded {}%", error_rate, max)
+            }
+            StopReason::ConsecutiveFailures { count } => {
+                write!(f, "{} consecutive requests failed", count)
+            }
+            StopReason::Latency {
+                percentile,
+                latency,
+                max,
+            } => write!(
+                f,
+                "p{} latency {:.1}ms exceeded {:.1}ms",
+                percentile,
+                latency.0 * 1000.0,
+                max.0 * 1000.0
+            ),
+            StopReason::WallTime { max } => write!(f, "Wall time exceeded {}s", max.0),
+        }
+    }
+}
+
+/// The marker record at the head of result files, which anchors the times of the results to the wall clock.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Started {
+    /// Wall-clock time at which the run started (i.e., the origin of `end_time`) in seconds since the Unix epoch.
+    pub unix_time: Seconds,
+}
+
+/// The marker record appended to the results of a run that was stopped early.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stopped {
+    pub end_time: Seconds,
+    #[serde(flatten)]
+    pub reason: StopReason,
+}
+
+/// An element of result files.
+///
+/// In the NDJSON format, each record is a line tagged by its `result` field
+/// (`"started"` and `"stopped"` for the markers).
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)] // The markers appear at most once per run.
+pub enum Record {
+    Result(RequestResult),
+    Started(Started),
+    Stopped(Stopped),
+}
+impl Serialize for Record {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            Record::Result(r) => r.serialize(serializer),
+            Record::Started(s) => Marker::Started(s).serialize(serializer),
+            Record::Stopped(s) => Marker::Stopped(s).serialize(serializer),
+        }
+    }
+}
+impl<'de> Deserialize<'de> for Record {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        use serde::de::Error as _;
+
+        // NOTE: Dispatching by the tag (instead of `#[serde(untagged)]`) reports why a record is invalid.
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let record = match value.get("result").and_then(|v| v.as_str()) {
+            Some("started") | Some("stopped") => match OwnedMarker::deserialize(value) {
+                Ok(OwnedMarker::Started(s)) => Ok(Record::Started(s)),
+                Ok(OwnedMarker::Stopped(s)) => Ok(Record::Stopped(s)),
+                Err(e) => Err(e),
+            },
+            _ => RequestResult::deserialize(value).map(Record::Result),
+        };
+        record.map_err(D::Error::custom)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+enum Marker<'a> {
+    Started(&'a Started),
+    Stopped(&'a Stopped),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+enum OwnedMarker {
+    Started(Started),
+    Stopped(Stopped),
+}
+
+/// The version of the JSON format of result files.
+///
+/// Version 1 is a plain array of results, which is written by hb 0.0.13 and earlier.
+pub const RESULTS_VERSION: u32 = 2;
+
+/// The JSON format of result files (the `version` field is checked before deserialization).
+#[derive(Deserialize)]
+struct JsonResults {
+    #[serde(default)]
+    started: Option<Started>,
+    results: Vec<RequestResult>,
+    #[serde(default)]
+    stopped: Vec<Stopped>,
+}
+
+/// `JsonResults` for serialization without copying the records.
+#[derive(Serialize)]
+struct JsonResultsRef<'a> {
+    version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    started: Option<&'a Started>,
+    results: Vec<&'a RequestResult>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stopped: Vec<&'a Stopped>,
+}
+
+/// The format of result files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultFormat {
+    /// A JSON object of the version `RESULTS_VERSION` (a plain array of results is also readable).
+    Json,
+
+    /// One record per line: the `Started` marker, the results and then the `Stopped` markers (if any).
+    Ndjson,
+}
+
+struct NdjsonWriter(Box<dyn Write + Send>);
+impl fmt::Debug for NdjsonWriter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NdjsonWriter(_)")
+    }
+}
+
+/// Reads results in the given format.
+///
+/// `Started` and `Stopped` markers are skipped.
+pub fn read_results<R: BufRead>(reader: R, format: ResultFormat) -> Result<Vec<RequestResult>> {
+    let records = track!(read_records(reader, format))?;
+    Ok(records
+        .into_iter()
+        .filter_map(|r| match r {
+            Record::Result(r) => Some(r),
+            Record::Started(_) | Record::Stopped(_) => None,
+        })
+        .collect())
+}
+
+/// Reads records in the given format.
+pub fn read_records<R: BufRead>(reader: R, format: ResultFormat) -> Result<Vec<Record>> {
+    let mut records = Vec::new();
+    match format {
+        ResultFormat::Json => {
+            let value: serde_json::Value = track!(serdeconv::from_json_reader(reader))?;
+            if value.is_array() {
+                let results: Vec<RequestResult> = track!(serde_json::from_value(value)
+                    .map_err(|e| Error::from(ErrorKind::Other.cause(e))))?;
+                records.extend(results.into_iter().map(Record::Result));
+                return Ok(records);
+            }
+            let version = value.get("version").and_then(|v| v.as_u64());
+            track_assert_eq!(
+                version,
+                Some(u64::from(RESULTS_VERSION)),
+                ErrorKind::Other,
+                "Unsupported version of the result format"
+            );
+            let results: JsonResults =
+                track!(serde_json::from_value(value)
+                    .map_err(|e| Error::from(ErrorKind::Other.cause(e))))?;
+            records.extend(results.started.map(Record::Started));
+            records.extend(results.results.into_iter().map(Record::Result));
+            records.extend(results.stopped.into_iter().map(Record::Stopped));
+        }
+        ResultFormat::Ndjson => {
+            for (i, line) in reader.lines().enumerate() {
+                let line = track!(line.map_err(Error::from))?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                records.push(track!(serdeconv::from_json_str(&line); i + 1)?);
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// Writes records in the given format.
+///
+/// In the JSON format, the `Started` marker is written only if it is the first record.
+pub fn write_records<W: Write>(
+    mut writer: W,
+    records: &[Record],
+    format: ResultFormat,
+) -> Result<()> {
+    match format {
+        ResultFormat::Json => {
+            let mut json = JsonResultsRef {
+                version: RESULTS_VERSION,
+                started: None,
+                results: Vec::new(),
+                stopped: Vec::new(),
+            };
+            for (i, record) in records.iter().enumerate() {
+                match record {
+                    Record::Result(r) => json.results.push(r),
+                    Record::Started(s) => {
+                        track_assert_eq!(
+                            i,
+                            0,
+                            ErrorKind::Other,
+                            "`Started` must be the first record"
+                        );
+                        json.started = Some(s);
+                    }
+                    Record::Stopped(s) => json.stopped.push(s),
+                }
+            }
+            track!(serdeconv::to_json_writer_pretty(&json, &mut writer))?;
+        }
+        ResultFormat::Ndjson => {
+            for record in records {
+                track!(serdeconv::to_json_writer(record, &mut writer))?;
+                track!(writer.write_all(b"\n").map_err(Error::from))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+impl RequestResult {
+    /// Makes a successful result with the status 200 and no labels.
+    pub(crate) fn ok_for_test(seq_no: usize, end_time: f64, elapsed: f64) -> Self {
+        RequestResult::Ok {
+            seq_no,
+            end_time: Seconds(end_time),
+            elapsed: Seconds(elapsed),
+            scheduled_start_time: None,
+            labels: Labels::default(),
+            phases: None,
+            response: Response {
+                status: 200,
+                content_length: 0,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::request::Method;
+
+    fn request(start_time: f64) -> Request {
+        Request {
+            method: Method::Get,
+            url: "http://localhost/".parse().unwrap(),
+            content: None,
+            timeout: None,
+            start_time: Some(Seconds(start_time)),
+            headers: BTreeMap::new(),
+            expect: None,
+            name: None,
+            tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn request_queue_pulls_generator_lazily() {
+        let queue = RequestQueue::with_generator((0..1000).map(|i| request(i as f64)));
+        let (seq_no, first) = queue.pop().unwrap().unwrap();
+        assert_eq!(seq_no, 0);
+
+        // The requests pushed back by waiting clients are popped before the later generated ones.
+        for _ in 0..100 {
+            queue.push(seq_no, first.clone()).unwrap();
+            assert_eq!(queue.pop().unwrap().unwrap().0, 0);
+        }
+        queue.push(seq_no, first).unwrap();
+        assert_eq!(queue.requests.lock().unwrap().heap.len(), 1);
+
+        let seq_nos = (0..3)
+            .map(|_| queue.pop().unwrap().unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(seq_nos, [0, 1, 2]);
+        assert!(queue.requests.lock().unwrap().heap.is_empty());
+    }
+
+    #[test]
+    fn records_are_written_and_read_in_both_formats() {
+        let records = vec![
+            Record::Started(Started {
+                unix_time: Seconds(1000.0),
+            }),
+            Record::Result(RequestResult::ok_for_test(0, 1.0, 0.5)),
+            Record::Stopped(Stopped {
+                end_time: Seconds(2.0),
+                reason: StopReason::Interrupted,
+            }),
+        ];
+        for format in [ResultFormat::Json, ResultFormat::Ndjson] {
+            let mut buf = Vec::new();
+            write_records(&mut buf, &records, format).unwrap();
+            let read = read_records(&buf[..], format).unwrap();
+            assert_eq!(read.len(), 3);
+            assert!(matches!(read[0], Record::Started(ref s) if s.unix_time == Seconds(1000.0)));
+            assert!(matches!(read[1], Record::Result(ref r) if r.seq_no() == 0));
+            assert!(matches!(
+                read[2],
+                Record::Stopped(ref s) if matches!(s.reason, StopReason::Interrupted)
+            ));
+        }
+
+        let mut json = Vec::new();
+        write_records(&mut json, &records, ResultFormat::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["version"], RESULTS_VERSION);
+        assert_eq!(json["started"]["unix_time"], 1000.0);
+        assert_eq!(json["results"][0]["result"], "ok");
+        assert_eq!(json["stopped"][0]["reason"], "interrupted");
+    }
+
+    #[test]
+    fn legacy_and_invalid_records_are_handled() {
+        let legacy = br#"[{"result": "ok", "seq_no": 0, "end_time": 1.0, "elapsed": 0.5,
+                          "response": {"status": 200, "content_length": 0}}]"#;
+        let records = read_records(&legacy[..], ResultFormat::Json).unwrap();
+        assert!(matches!(records[..], [Record::Result(_)]));
+
+        let future = br#"{"version": 3, "results": []}"#;
+        assert!(read_records(&future[..], ResultFormat::Json).is_err());
+
+        // The NDJSON format is not guessed from the content.
+        let ndjson = b"{\"result\": \"started\", \"unix_time\": 1.0}\n";
+        assert!(read_records(&ndjson[..], ResultFormat::Ndjson).is_ok());
+        assert!(read_records(&legacy[..], ResultFormat::Ndjson).is_err());
+
+        let e = read_records(
+            &b"{\"result\": \"ok\", \"seq_no\": 0}"[..],
+            ResultFormat::Ndjson,
+        )
+        .unwrap_err();
+        assert!(e.to_string().contains("missing field"), "{}", e);
+    }
+}
@@ -0,0 +1,260 @@
+//! Search for the maximum load that a server can sustain.
+use crate::profile::ProfileKind;
+use crate::run::{RequestResult, Seconds};
+use crate::summary::SummaryBuilder;
+use crate::{ErrorKind, Result};
+use serde::Serialize;
+
+/// Limits which the measurements of a sustainable load must satisfy.
+///
+/// `None` disables the corresponding check.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// Percentile of the latency to check.
+    pub percentile: f64,
+
+    /// Maximum allowed latency at `percentile`.
+    pub max_latency: Option<Seconds>,
+
+    /// Maximum allowed error rate in percent.
+    pub max_error_rate: Option<f64>,
+}
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            percentile: 99.0,
+            max_latency: None,
+            max_error_rate: None,
+        }
+    }
+}
+
+/// Increases the load from `start` (doubling it, or adding `step` if given) until a limit is breached,
+/// then narrows the boundary down by binary search until it is within `resolution`.
+#[derive(Debug, Clone)]
+pub struct SaturationSearchBuilder {
+    kind: ProfileKind,
+    start: usize,
+    max: usize,
+    step: Option<usize>,
+    resolution: usize,
+    limits: Limits,
+}
+impl SaturationSearchBuilder {
+    pub fn new(kind: ProfileKind) -> Self {
+        SaturationSearchBuilder {
+            kind,
+            start: 1,
+            max: 10_000,
+            step: None,
+            resolution: 1,
+            limits: Limits::default(),
+        }
+    }
+    pub fn start(&mut self, load: usize) -> &mut Self {
+        self.start = load;
+        self
+    }
+    pub fn max(&mut self, load: usize) -> &mut Self {
+        self.max = load;
+        self
+    }
+    pub fn step(&mut self, step: usize) -> &mut Self {
+        self.step = Some(step);
+        self
+    }
+    pub fn resolution(&mut self, resolution: usize) -> &mut Self {
+        self.resolution = resolution;
+        self
+    }
+    pub fn limits(&mut self, limits: Limits) -> &mut Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Executes the search.
+    ///
+    /// `trial` runs a benchmark at the given load (i.e., the concurrency or the rate) and returns its results.
+    /// If it returns `None`, the search is aborted and the measurements made so far are reported.
+    pub fn finish<F>(&self, mut trial: F) -> Result<SaturationReport>
+    where
+        F: FnMut(usize) -> Result<Option<Vec<RequestResult>>>,
+    {
+        track_assert!(self.start > 0, ErrorKind::Other);
+        track_assert!(self.start <= self.max, ErrorKind::Other);
+        track_assert_ne!(self.step, Some(0), ErrorKind::Other);
+
+        let mut measurements = Vec::new();
+        let mut sustainable = 0;
+        let mut breached = None;
+        let mut load = self.start;
+        let mut aborted = false;
+        loop {
+            let m = match track!(trial(load))? {
+                Some(results) => self.measure(load, results),
+                None => {
+                    aborted = true;
+                    break;
+                }
+            };
+            let ok = m.is_sustainable();
+            measurements.push(m);
+            if !ok {
+                breached = Some(load);
+                break;
+            }
+            sustainable = load;
+            if load == self.max {
+                break;
+            }
+            load = self
+                .step
+                .map_or(load.saturating_mul(2), |s| load.saturating_add(s))
+                .min(self.max);
+        }
+
+        while let (false, Some(upper)) = (aborted, breached) {
+            if upper - sustainable <= self.resolution {
+                break;
+            }
+            let load = sustainable + (upper - sustainable) / 2;
+            let m = match track!(trial(load))? {
+                Some(results) => self.measure(load, results),
+                None => break,
+            };
+            if m.is_sustainable() {
+                sustainable = load;
+            } else {
+                breached = Some(load);
+            }
+            measurements.push(m);
+        }
+
+        measurements.sort_by_key(|m| m.load);
+        let max_sustainable = measurements
+            .iter()
+            .find(|m| m.load == sustainable && m.is_sustainable())
+            .cloned();
+        Ok(SaturationReport {
+            kind: self.kind,
+            limits_breached_at: breached,
+            max_sustainable,
+            measurements,
+        })
+    }
+
+    fn measure(&self, load: usize, results: Vec<RequestResult>) -> Measurement {
+        if results.is_empty() {
+            return Measurement {
+                load,
+                requests: 0,
+                rps: 0.0,
+                error_rate: 0.0,
+                latency: Seconds(0.0),
+                violations: vec!["No requests completed".to_owned()],
+            };
+        }
+
+        let p = self.limits.percentile;
+        let summary = SummaryBuilder::new().percentiles(vec![p]).finish(results);
+
+        // NOTE: The corrected latency equals the raw one unless the requests are scheduled (i.e., rate trials).
+        let latency = summary.corrected_latency.percentiles.0[0].1;
+        let error_rate = summary.error_rate() * 100.0;
+        let mut violations = Vec::new();
+        if let Some(max) = self.limits.max_latency {
+            if latency > max {
+                violations.push(format!(
+                    "p{} latency {:.1}ms exceeds {:.1}ms",
+                    p,
+                    latency.0 * 1000.0,
+                    max.0 * 1000.0
+                ));
+            }
+        }
+        if let Some(max) = self.limits.max_error_rate {
+            if error_rate > max {
+                violations.push(format!("Error rate {:.2}% exceeds {}%", error_rate, max));
+            }
+        }
+        Measurement {
+            load,
+            requests: summary.count.total,
+            rps: summary.rps,
+            error_rate,
+            latency,
+            violations,
+        }
+    }
+}
+
+/// The result of a trial run at a load.
+#[derive(Debug, Clone, Serialize)]
+pub struct Measurement {
+    /// Concurrency or rate of the trial.
+    pub load: usize,
+    pub requests: usize,
+    pub rps: f64,
+
+    /// Error rate in percent.
+    pub error_rate: f64,
+
+    /// Latency at the percentile given by `Limits::percentile`.
+    pub latency: Seconds,
+
+    /// Descriptions of the breached limits.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<String>,
+}
+impl Measurement {
+    pub fn is_sustainable(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SaturationReport {
+    pub kind: ProfileKind,
+
+    /// The trial at the highest load that satisfied all the limits.
+    pub max_sustainable: Option<Measurement>,
+
+    /// The lowest load at which a limit was breached (`None` if the maximum load was sustained).
+    pub limits_breached_at: Option<usize>,
+
+    /// The measurements ordered by load.
+    pub measurements: Vec<Measurement>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn results(latency: f64) -> Vec<RequestResult> {
+        (0..10)
+            .map(|seq_no| RequestResult::ok_for_test(seq_no, 1.0, latency))
+            .collect()
+    }
+
+    #[test]
+    fn saturation_search_works() {
+        let mut builder = SaturationSearchBuilder::new(ProfileKind::Concurrency);
+        builder.resolution(2).limits(Limits {
+            max_latency: Some(Seconds(0.1)),
+            ..Limits::default()
+        });
+
+        // The latency exceeds the limit above the concurrency of 37.
+        let mut loads = Vec::new();
+        let report = builder
+            .finish(|load| {
+                loads.push(load);
+                Ok(Some(results(if load > 37 { 0.2 } else { 0.05 })))
+            })
+            .unwrap();
+        assert_eq!(loads, [1, 2, 4, 8, 16, 32, 64, 48, 40, 36, 38]);
+        assert_eq!(report.max_sustainable.unwrap().load, 36);
+        assert_eq!(report.limits_breached_at, Some(38));
+        assert_eq!(report.measurements.len(), loads.len());
+    }
+}
@@ -1,53 +1,379 @@
-use crate::run::{RequestResult, Seconds};
-use serde::Serialize;
+use crate::expect::Rule;
+use crate::histogram::{Interval, LatencyHistogram, CORRECTED_TAG};
+use crate::run::{Phases, RequestResult, Seconds};
+use crate::ErrorKind;
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
 use std::collections::BTreeMap;
 
+pub const DEFAULT_PERCENTILES: [f64; 7] = [50.0, 75.0, 90.0, 95.0, 99.0, 99.9, 99.99];
+
+#[derive(Debug, Clone)]
+pub struct SummaryBuilder {
+    percentiles: Vec<f64>,
+}
+impl SummaryBuilder {
+    pub fn new() -> Self {
+        SummaryBuilder::default()
+    }
+    pub fn percentiles(&mut self, percentiles: Vec<f64>) -> &mut Self {
+        self.percentiles = percentiles;
+        self
+    }
+    pub fn finish(&self, results: Vec<RequestResult>) -> Summary {
+        self.summarize(&results)
+    }
+
+    /// Makes a summary per group (sorted by the group keys).
+    ///
+    /// A result is counted in all of its keys.
+    /// The `duration` (and `rps`) of each group is measured from the start of its first request.
+    pub fn finish_grouped(&self, results: Vec<RequestResult>, by: GroupBy) -> Vec<GroupSummary> {
+        let mut groups = BTreeMap::<String, Accumulator>::new();
+        for r in &results {
+            for group in by.keys(r) {
+                groups.entry(group).or_default().add(r);
+            }
+        }
+        groups
+            .into_iter()
+            .map(|(group, acc)| GroupSummary {
+                group,
+                summary: acc.finish_group(&self.percentiles),
+            })
+            .collect()
+    }
+
+    /// Merges the interval histograms (e.g., read from the interval logs of multiple runs) into a summary.
+    ///
+    /// Untagged histograms are the raw latencies and those tagged with `CORRECTED_TAG` are the corrected ones.
+    /// Histograms with other tags are ignored.
+    pub fn finish_histograms(&self, intervals: &[Interval]) -> crate::Result<HistogramSummary> {
+        let mut latency = LatencyHistogram::new();
+        let mut corrected_latency = LatencyHistogram::new();
+        for interval in intervals {
+            match interval.tag.as_deref() {
+                None => track!(latency.add(&interval.histogram))?,
+                Some(CORRECTED_TAG) => track!(corrected_latency.add(&interval.histogram))?,
+                Some(_) => {}
+            }
+        }
+        Ok(HistogramSummary {
+            count: latency.len(),
+            latency: Latency::from_histogram(&latency, &self.percentiles),
+            corrected_latency: if corrected_latency.is_empty() {
+                None
+            } else {
+                Some(Latency::from_histogram(
+                    &corrected_latency,
+                    &self.percentiles,
+                ))
+            },
+        })
+    }
+
+    fn summarize(&self, results: &[RequestResult]) -> Summary {
+        let mut total = Accumulator::default();
+        let mut stages: Vec<(String, Accumulator)> = Vec::new();
+        let mut names = BTreeMap::<String, Accumulator>::new();
+        for r in results {
+            total.add(r);
+            if let Some(stage) = r.stage() {
+                // NOTE: The stages appear one after another, so the last one is the most likely match.
+                match stages.iter_mut().rev().find(|s| s.0 == stage) {
+                    Some((_, acc)) => acc.add(r),
+                    None => {
+                        let mut acc = Accumulator::default();
+                        acc.add(r);
+                        stages.push((stage.to_owned(), acc));
+                    }
+                }
+            }
+            if let Some(name) = r.name() {
+                if let Some(acc) = names.get_mut(name) {
+                    acc.add(r);
+                } else {
+                    names.entry(name.to_owned()).or_default().add(r);
+                }
+            }
+        }
+
+        let mut summary = total.finish(&self.percentiles);
+        summary.stages = stages
+            .into_iter()
+            .map(|(stage, acc)| StageSummary {
+                stage,
+                summary: acc.finish_group(&self.percentiles),
+            })
+            .collect();
+        summary.names = names
+            .into_iter()
+            .map(|(name, acc)| NameSummary {
+                name,
+                summary: acc.finish_group(&self.percentiles),
+            })
+            .collect();
+        summary
+    }
+}
+impl Default for SummaryBuilder {
+    fn default() -> Self {
+        SummaryBuilder {
+            percentiles: DEFAULT_PERCENTILES.to_vec(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct Summary {
     pub count: Count,
     pub status: BTreeMap<u16, usize>,
+
+    /// Number of the errors by kind.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub errors: BTreeMap<ErrorKind, usize>,
+
+    /// Number of the assertion failures by violated rule.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub assertion_failures: BTreeMap<Rule, usize>,
     pub duration: Seconds,
     pub rps: f64,
     pub latency: Latency,
+    pub corrected_latency: Latency,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phases: Option<PhasesSummary>,
+
+    /// Summaries of the load profile stages (in order of appearance).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stages: Vec<StageSummary>,
+
+    /// Summaries of the requests with each name (i.e., `Request::name`).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub names: Vec<NameSummary>,
 }
 impl Summary {
-    pub fn new(results: Vec<RequestResult>) -> Self {
-        let count = Count::new(&results);
-        let duration = results.iter().map(|r| r.end_time()).max().unwrap();
-        let latency = Latency::new(&results);
-        let mut status = BTreeMap::new();
-        for r in results {
-            if let RequestResult::Ok { ref response, .. } = r {
-                *status.entry(response.status).or_insert(0) += 1;
-            }
-        }
-        Summary {
-            count,
-            status,
-            rps: count.total as f64 / duration.0,
-            duration,
-            latency,
+    /// Returns the ratio of the requests that did not succeed (i.e., errors and assertion failures).
+    ///
+    /// This is zero if there are no requests.
+    pub fn error_rate(&self) -> f64 {
+        if self.count.total == 0 {
+            return 0.0;
         }
+        (self.count.total - self.count.ok) as f64 / self.count.total as f64
+    }
+    pub fn new(results: Vec<RequestResult>) -> Self {
+        SummaryBuilder::new().finish(results)
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize)]
+/// The summary of latency histograms (see `SummaryBuilder::finish_histograms`).
+#[derive(Debug, Serialize)]
+pub struct HistogramSummary {
+    pub count: u64,
+    pub latency: Latency,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub corrected_latency: Option<Latency>,
+}
+
+/// The summary of the results of a load profile stage.
+///
+/// Its `duration` is measured from the start of the first request of the stage.
+#[derive(Debug, Serialize)]
+pub struct StageSummary {
+    pub stage: String,
+    #[serde(flatten)]
+    pub summary: Summary,
+}
+
+/// An attribute by which results are grouped (see `SummaryBuilder::finish_grouped`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    Url,
+    Method,
+
+    /// The status code (or `"error"` if no response was received).
+    Status,
+
+    /// Each tag (a result with multiple tags belongs to multiple groups).
+    Tag,
+    Name,
+
+    /// The result file from which the result was merged (see `merge`).
+    Source,
+}
+impl GroupBy {
+    /// Results without the attribute (e.g., results of older versions) are grouped into `"(none)"`.
+    fn keys(self, result: &RequestResult) -> Vec<String> {
+        let labels = result.labels();
+        let key = match self {
+            GroupBy::Url => labels.url.clone(),
+            GroupBy::Method => labels.method.clone(),
+            GroupBy::Status => Some(
+                result
+                    .response()
+                    .map_or_else(|| "error".to_owned(), |r| r.status.to_string()),
+            ),
+            GroupBy::Tag if !labels.tags.is_empty() => return labels.tags.clone(),
+            GroupBy::Tag => None,
+            GroupBy::Name => labels.name.clone(),
+            GroupBy::Source => labels.source.clone(),
+        };
+        vec![key.unwrap_or_else(|| "(none)".to_owned())]
+    }
+}
+
+/// The summary of the results of a group.
+#[derive(Debug, Serialize)]
+pub struct GroupSummary {
+    pub group: String,
+    #[serde(flatten)]
+    pub summary: Summary,
+}
+
+/// The summary of the results of the requests with a name.
+///
+/// Its `duration` is measured from the start of the first request with the name.
+#[derive(Debug, Serialize)]
+pub struct NameSummary {
+    pub name: String,
+    #[serde(flatten)]
+    pub summary: Summary,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
 pub struct Count {
     pub total: usize,
     pub ok: usize,
     pub error: usize,
+    pub assertion_failed: usize,
 }
 impl Count {
-    fn new(results: &[RequestResult]) -> Self {
-        let ok = results.iter().filter(|r| r.is_ok()).count();
-        Count {
-            total: results.len(),
-            ok,
-            error: results.len() - ok,
+    fn add(&mut self, r: &RequestResult) {
+        self.total += 1;
+        if r.is_ok() {
+            self.ok += 1;
+        } else if r.is_assertion_failed() {
+            self.assertion_failed += 1;
+        } else {
+            self.error += 1;
         }
     }
 }
 
+/// Statistics of the results added so far, from which a `Summary` is made.
+#[derive(Debug, Default)]
+struct Accumulator {
+    count: Count,
+    start: Option<Seconds>,
+    end: Seconds,
+    latency: LatencyHistogram,
+    corrected_latency: LatencyHistogram,
+    phases: PhasesAccumulator,
+    status: BTreeMap<u16, usize>,
+    errors: BTreeMap<ErrorKind, usize>,
+    assertion_failures: BTreeMap<Rule, usize>,
+}
+impl Accumulator {
+    fn add(&mut self, r: &RequestResult) {
+        self.count.add(r);
+        let start = r.start_time();
+        self.start = Some(self.start.map_or(start, |s| s.min(start)));
+        self.end = self.end.max(r.end_time());
+        self.latency.record(r.elapsed());
+        self.corrected_latency.record(r.corrected_elapsed());
+        if let Some(phases) = r.phases() {
+            self.phases.add(phases);
+        }
+        if let Some(kind) = r.error_kind() {
+            *self.errors.entry(kind).or_insert(0) += 1;
+        }
+        if let Some(response) = r.response() {
+            *self.status.entry(response.status).or_insert(0) += 1;
+        }
+        if let RequestResult::AssertionFailed { failure, .. } = r {
+            *self.assertion_failures.entry(failure.rule).or_insert(0) += 1;
+        }
+    }
+
+    /// Makes a summary whose `duration` is measured from the start of the run.
+    fn finish(self, percentiles: &[f64]) -> Summary {
+        Summary {
+            count: self.count,
+            status: self.status,
+            errors: self.errors,
+            assertion_failures: self.assertion_failures,
+            rps: rps(self.count.total, self.end),
+            duration: self.end,
+            latency: Latency::from_histogram(&self.latency, percentiles),
+            corrected_latency: Latency::from_histogram(&self.corrected_latency, percentiles),
+            phases: self.phases.finish(percentiles),
+            stages: Vec::new(),
+            names: Vec::new(),
+        }
+    }
+
+    /// Makes a summary whose `duration` is measured from the start of the first request.
+    fn finish_group(self, percentiles: &[f64]) -> Summary {
+        let start = self.start.unwrap_or_default();
+        let mut summary = self.finish(percentiles);
+        summary.duration = Seconds(summary.duration.0 - start.0);
+        summary.rps = rps(summary.count.total, summary.duration);
+        summary
+    }
+}
+
+/// Latency breakdown by phase of the requests for which phase timings were recorded.
+#[derive(Debug, Serialize)]
+pub struct PhasesSummary {
+    pub count: usize,
+    pub dns: Latency,
+    pub connect: Latency,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<Latency>,
+    pub ttfb: Latency,
+    pub transfer: Latency,
+}
+
+#[derive(Debug, Default)]
+struct PhasesAccumulator {
+    count: usize,
+    dns: LatencyHistogram,
+    connect: LatencyHistogram,
+    tls: LatencyHistogram,
+    ttfb: LatencyHistogram,
+    transfer: LatencyHistogram,
+}
+impl PhasesAccumulator {
+    fn add(&mut self, p: &Phases) {
+        self.count += 1;
+        self.dns.record(p.dns);
+        self.connect.record(p.connect);
+        if let Some(t) = p.tls {
+            self.tls.record(t);
+        }
+        self.ttfb.record(p.ttfb);
+        self.transfer.record(p.transfer);
+    }
+
+    fn finish(&self, percentiles: &[f64]) -> Option<PhasesSummary> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(PhasesSummary {
+            count: self.count,
+            dns: Latency::from_histogram(&self.dns, percentiles),
+            connect: Latency::from_histogram(&self.connect, percentiles),
+            tls: if self.tls.is_empty() {
+                None
+            } else {
+                Some(Latency::from_histogram(&self.tls, percentiles))
+            },
+            ttfb: Latency::from_histogram(&self.ttfb, percentiles),
+            transfer: Latency::from_histogram(&self.transfer, percentiles),
+        })
+    }
+}
+
 #[derive(Debug, Default, Serialize)]
 pub struct Latency {
     pub min: Seconds,
@@ -56,36 +382,76 @@ pub struct Latency {
     pub max: Seconds,
     pub var: f64,
     pub sd: f64,
+    pub percentiles: Percentiles,
 }
 impl Latency {
-    fn new(results: &[RequestResult]) -> Self {
-        if results.is_empty() {
+    pub fn from_histogram(histogram: &LatencyHistogram, percentiles: &[f64]) -> Self {
+        if histogram.is_empty() {
             return Latency::default();
         }
-        let mut times = results.iter().map(|r| r.elapsed()).collect::<Vec<_>>();
-        times.sort();
 
-        let var = unbiased_variance(&times);
+        let var = histogram.variance();
         Latency {
-            min: times[0],
-            median: times[times.len() / 2],
-            mean: Seconds(times.iter().map(|t| t.0).sum::<f64>() / times.len() as f64),
-            max: *times.last().unwrap(),
+            min: histogram.min(),
+            median: histogram.percentile(50.0),
+            mean: histogram.mean(),
+            max: histogram.max(),
             var,
             sd: var.sqrt(),
+            percentiles: Percentiles(
+                percentiles
+                    .iter()
+                    .map(|&p| (p, histogram.percentile(p)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Latencies at the given percentiles, serialized as a map such as `{"p50": 0.01, "p99.9": 0.2}`.
+#[derive(Debug, Default, Clone)]
+pub struct Percentiles(pub Vec<(f64, Seconds)>);
+impl Percentiles {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+impl Serialize for Percentiles {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (p, latency) in &self.0 {
+            map.serialize_entry(&format!("p{}", p), latency)?;
         }
+        map.end()
     }
 }
 
-fn unbiased_variance(samples: &[Seconds]) -> f64 {
-    if samples.len() < 2 {
+fn rps(count: usize, duration: Seconds) -> f64 {
+    if duration.0 > 0.0 {
+        count as f64 / duration.0
+    } else {
+        0.0
+    }
+}
+
+/// Returns the unbiased variance of the samples given as `(value, count)` pairs.
+pub(crate) fn unbiased_variance<I>(samples: I) -> f64
+where
+    I: IntoIterator<Item = (Seconds, u64)>,
+{
+    let (n, sqsum, sum) = samples
+        .into_iter()
+        .fold((0.0, 0.0, 0.0), |(n, a, b), (s, count)| {
+            let count = count as f64;
+            (n + count, a + s.0 * s.0 * count, b + s.0 * count)
+        });
+    if n < 2.0 {
         return 0.0;
     }
 
-    let n = samples.len() as f64;
-    let (sqsum, sum) = samples
-        .iter()
-        .fold((0.0, 0.0), |(a, b), s| (a + s.0 * s.0, b + s.0));
     let avg = sum / n;
     (sqsum - n * avg * avg) / (n - 1.0)
 }
@@ -93,15 +459,141 @@ fn unbiased_variance(samples: &[Seconds]) -> f64 {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::run::Seconds;
+    use crate::run::{Labels, Response, Seconds};
+    use crate::Error;
+
+    fn result(end_time: f64, labels: Labels, status: Option<u16>) -> RequestResult {
+        match status {
+            Some(status) => RequestResult::Ok {
+                seq_no: 0,
+                end_time: Seconds(end_time),
+                elapsed: Seconds(0.5),
+                scheduled_start_time: None,
+                labels,
+                phases: None,
+                response: Response {
+                    status,
+                    content_length: 0,
+                },
+            },
+            None => RequestResult::Error {
+                seq_no: 0,
+                end_time: Seconds(end_time),
+                elapsed: Seconds(0.5),
+                scheduled_start_time: None,
+                labels,
+                error: Error::from(ErrorKind::Timeout),
+            },
+        }
+    }
+
+    fn labels(method: &str, url: &str, tags: &[&str]) -> Labels {
+        Labels {
+            method: Some(method.to_owned()),
+            url: Some(url.to_owned()),
+            tags: tags.iter().map(|t| (*t).to_owned()).collect(),
+            ..Labels::default()
+        }
+    }
+
+    fn groups(results: &[RequestResult], by: GroupBy) -> Vec<(String, usize)> {
+        SummaryBuilder::new()
+            .finish_grouped(results.to_vec(), by)
+            .into_iter()
+            .map(|g| (g.group, g.summary.count.total))
+            .collect()
+    }
+
+    #[test]
+    fn finish_grouped_works() {
+        let results = vec![
+            result(1.0, labels("GET", "http://a/", &["x"]), Some(200)),
+            result(2.0, labels("POST", "http://b/", &["x", "y"]), Some(500)),
+            result(3.0, labels("GET", "http://b/", &[]), None),
+            result(4.0, Labels::default(), Some(200)),
+        ];
+        let pair = |k: &str, n| (k.to_owned(), n);
+
+        assert_eq!(
+            groups(&results, GroupBy::Url),
+            [
+                pair("(none)", 1),
+                pair("http://a/", 1),
+                pair("http://b/", 2)
+            ]
+        );
+        assert_eq!(
+            groups(&results, GroupBy::Method),
+            [pair("(none)", 1), pair("GET", 2), pair("POST", 1)]
+        );
+        assert_eq!(
+            groups(&results, GroupBy::Status),
+            [pair("200", 2), pair("500", 1), pair("error", 1)]
+        );
+        assert_eq!(
+            groups(&results, GroupBy::Tag),
+            [pair("(none)", 2), pair("x", 2), pair("y", 1)]
+        );
+
+        // The duration of a group is measured from the start of its first request.
+        let summaries = SummaryBuilder::new().finish_grouped(results, GroupBy::Url);
+        assert_eq!(summaries[2].summary.duration, Seconds(1.5));
+        assert_eq!(summaries[2].summary.count.error, 1);
+        assert_eq!(summaries[2].summary.rps, 2.0 / 1.5);
+    }
+
+    #[test]
+    fn stages_and_names_work() {
+        let labeled = |stage: &str, name: Option<&str>| Labels {
+            stage: Some(stage.to_owned()),
+            name: name.map(str::to_owned),
+            ..Labels::default()
+        };
+        let results = vec![
+            result(1.0, labeled("warmup", Some("b")), Some(200)),
+            result(2.0, labeled("warmup", Some("a")), Some(200)),
+            result(3.0, labeled("peak", Some("b")), None),
+            result(4.0, labeled("peak", None), Some(200)),
+        ];
+        let summary = SummaryBuilder::new().finish(results);
+        assert_eq!(summary.count.total, 4);
+        assert_eq!(summary.duration, Seconds(4.0));
+
+        let stages = summary
+            .stages
+            .iter()
+            .map(|s| (s.stage.as_str(), s.summary.count.total, s.summary.duration))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stages,
+            [("warmup", 2, Seconds(1.5)), ("peak", 2, Seconds(1.5))]
+        );
+
+        let names = summary
+            .names
+            .iter()
+            .map(|n| (n.name.as_str(), n.summary.count.total))
+            .collect::<Vec<_>>();
+        assert_eq!(names, [("a", 1), ("b", 2)]);
+        assert_eq!(summary.names[1].summary.count.error, 1);
+    }
 
     #[test]
     fn var_works() {
         let samples = [0.7, -1.6, -0.2, -1.2, -0.1, 3.4, 3.7, 0.8, 0.0, 2.0]
             .iter()
             .cloned()
-            .map(Seconds)
+            .map(|s| (Seconds(s), 1))
             .collect::<Vec<_>>();
-        assert_eq!((unbiased_variance(&samples) * 10000.0).floor(), 32005.0);
+        assert_eq!((unbiased_variance(samples) * 10000.0).floor(), 32005.0);
+    }
+
+    #[test]
+    fn empty_summary_works() {
+        let summary = SummaryBuilder::new().finish(Vec::new());
+        assert_eq!(summary.count.total, 0);
+        assert_eq!(summary.duration, Seconds(0.0));
+        assert_eq!(summary.rps, 0.0);
+        assert_eq!(summary.error_rate(), 0.0);
     }
 }
@@ -0,0 +1,549 @@
+//! Request templates whose URL, headers and body are expanded per request.
+//!
+//! Placeholders are written as `{{name}}` (or `{{name.column}}` for CSV variables).
+use crate::expect::Expect;
+use crate::request::{validate_header, Content, Method, Request};
+use crate::run::Seconds;
+use crate::{Error, ErrorKind, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// The format of template files.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateFile {
+    /// Seed of the random variables.
+    #[serde(default)]
+    pub seed: Option<u64>,
+
+    /// Number of requests to generate (the templates are used in turn).
+    #[serde(default)]
+    pub count: Option<usize>,
+
+    #[serde(default)]
+    pub variables: BTreeMap<String, Variable>,
+
+    pub requests: Vec<RequestTemplate>,
+}
+impl TemplateFile {
+    pub fn read_from<R: Read>(reader: R) -> Result<Self> {
+        let file = track!(serdeconv::from_json_reader(reader))?;
+        Ok(file)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Variable {
+    /// A random integer in `min..=max`.
+    RandomInt { min: i64, max: i64 },
+
+    /// A random (version 4) UUID.
+    Uuid,
+
+    /// `start`, `start + step`, `start + step * 2`, ...
+    Sequence {
+        #[serde(default)]
+        start: i64,
+        #[serde(default = "default_step")]
+        step: i64,
+    },
+
+    /// A row of a CSV file whose first line is the header.
+    ///
+    /// Rows are used in order (or at random if `random` is `true`).
+    Csv {
+        path: PathBuf,
+        #[serde(default)]
+        random: bool,
+    },
+}
+
+fn default_step() -> i64 {
+    1
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RequestTemplate {
+    pub method: Method,
+    pub url: String,
+    #[serde(default)]
+    pub content: Option<Content>,
+    #[serde(default)]
+    pub timeout: Option<Seconds>,
+    #[serde(default)]
+    pub start_time: Option<Seconds>,
+    #[serde(default, deserialize_with = "crate::request::deserialize_headers")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub expect: Option<Expect>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// An iterator that expands templates into requests.
+#[derive(Debug)]
+pub struct RequestGenerator {
+    templates: Vec<CompiledTemplate>,
+    variables: Vec<VariableState>,
+    rng: Rng,
+    seed: u64,
+    remaining: Option<usize>,
+    seq_no: usize,
+}
+impl RequestGenerator {
+    /// Makes a new generator.
+    ///
+    /// If `seed` is `None`, the seed of `file` (or the current time if it is also `None`) is used.
+    /// If `count` is `None`, the count of `file` is used, and if it is also `None`,
+    /// each template is used once unless `unlimited` is `true`.
+    pub fn new(
+        file: TemplateFile,
+        seed: Option<u64>,
+        count: Option<usize>,
+        unlimited: bool,
+    ) -> Result<Self> {
+        track_assert!(!file.requests.is_empty(), ErrorKind::Other, "No templates");
+
+        let names = file.variables.keys().cloned().collect::<Vec<_>>();
+        let mut variables = Vec::new();
+        for (name, variable) in file.variables {
+            variables.push(track!(VariableState::new(variable); name)?);
+        }
+        let templates = file
+            .requests
+            .into_iter()
+            .map(|t| track!(CompiledTemplate::new(t, &names, &variables)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let seed = seed.or(file.seed).unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64)
+        });
+        let remaining = count.or(file.count).or(if unlimited {
+            None
+        } else {
+            Some(templates.len())
+        });
+        Ok(RequestGenerator {
+            templates,
+            variables,
+            rng: Rng::new(seed),
+            seed,
+            remaining,
+            seq_no: 0,
+        })
+    }
+
+    /// Returns the seed used to generate random values.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn expand(&mut self) -> Result<Request> {
+        let rng = &mut self.rng;
+        let values = self
+            .variables
+            .iter_mut()
+            .map(|v| v.next(rng))
+            .collect::<Vec<_>>();
+        let template = &self.templates[self.seq_no % self.templates.len()];
+        track!(template.expand(&self.variables, &values))
+    }
+}
+/// Yields an error if a template cannot be expanded (e.g., the expanded URL is invalid).
+impl Iterator for RequestGenerator {
+    type Item = Result<Request>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(remaining) = self.remaining.as_mut() {
+            if *remaining == 0 {
+                return None;
+            }
+            *remaining -= 1;
+        }
+        let result = self.expand();
+        self.seq_no += 1;
+        Some(track!(result; self.seq_no - 1))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.remaining {
+            Some(n) => (n, Some(n)),
+            None => (0, None),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum VariableState {
+    RandomInt {
+        min: i64,
+        max: i64,
+    },
+    Uuid,
+    Sequence {
+        next: i64,
+        step: i64,
+    },
+    Csv {
+        table: CsvTable,
+        random: bool,
+        next: usize,
+    },
+}
+impl VariableState {
+    fn new(variable: Variable) -> Result<Self> {
+        Ok(match variable {
+            Variable::RandomInt { min, max } => {
+                track_assert!(min <= max, ErrorKind::Other; min, max);
+                VariableState::RandomInt { min, max }
+            }
+            Variable::Uuid => VariableState::Uuid,
+            Variable::Sequence { start, step } => VariableState::Sequence { next: start, step },
+            Variable::Csv { path, random } => {
+                let text = track!(fs::read_to_string(&path).map_err(Error::from); path)?;
+                let table = track!(CsvTable::parse(&text); path)?;
+                VariableState::Csv {
+                    table,
+                    random,
+                    next: 0,
+                }
+            }
+        })
+    }
+
+    fn next(&mut self, rng: &mut Rng) -> Value {
+        match self {
+            VariableState::RandomInt { min, max } => {
+                let range = (*max as i128 - *min as i128 + 1) as u128;
+                let offset = (rng.next_u64() as u128 % range) as i128;
+                Value::Text((*min as i128 + offset).to_string())
+            }
+            VariableState::Uuid => {
+                let bytes = (u128::from(rng.next_u64()) << 64) | u128::from(rng.next_u64());
+                let bytes =
+                    (bytes & !(0xF000 << 64) & !(0xC000 << 48)) | (0x4000 << 64) | (0x8000 << 48);
+                let hex = format!("{:032x}", bytes);
+                Value::Text(format!(
+                    "{}-{}-{}-{}-{}",
+                    &hex[0..8],
+                    &hex[8..12],
+                    &hex[12..16],
+                    &hex[16..20],
+                    &hex[20..32]
+                ))
+            }
+            VariableState::Sequence { next, step } => {
+                let value = *next;
+                *next += *step;
+                Value::Text(value.to_string())
+            }
+            VariableState::Csv {
+                table,
+                random,
+                next,
+            } => {
+                let row = if *random {
+                    (rng.next_u64() % table.rows.len() as u64) as usize
+                } else {
+                    let row = *next;
+                    *next = (*next + 1) % table.rows.len();
+                    row
+                };
+                Value::Row(row)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Value {
+    Text(String),
+    Row(usize),
+}
+
+#[derive(Debug)]
+struct CompiledTemplate {
+    method: Method,
+    url: Text,
+    content: Option<CompiledContent>,
+    timeout: Option<Seconds>,
+    start_time: Option<Seconds>,
+    headers: Vec<(String, Text)>,
+    expect: Option<Expect>,
+    name: Option<String>,
+    tags: Vec<String>,
+}
+impl CompiledTemplate {
+    fn new(
+        template: RequestTemplate,
+        names: &[String],
+        variables: &[VariableState],
+    ) -> Result<Self> {
+        let compile = |s: &str| track!(Text::parse(s, names, variables); s);
+        let content = match template.content {
+            None => None,
+            Some(Content::Size(size)) => Some(CompiledContent::Size(size)),
+            Some(Content::Text(text)) => Some(CompiledContent::Text(track!(compile(&text))?)),
+        };
+        let mut headers = Vec::new();
+        for (name, value) in template.headers {
+            headers.push((name, track!(compile(&value))?));
+        }
+        Ok(CompiledTemplate {
+            method: template.method,
+            url: track!(compile(&template.url))?,
+            content,
+            timeout: template.timeout,
+            start_time: template.start_time,
+            headers,
+            expect: template.expect,
+            name: template.name,
+            tags: template.tags,
+        })
+    }
+
+    fn expand(&self, variables: &[VariableState], values: &[Value]) -> Result<Request> {
+        let url = self.url.expand(variables, values);
+        let url = track!(Url::parse(&url).map_err(Error::from); url)?;
+        let mut headers = BTreeMap::new();
+        for (name, value) in &self.headers {
+            let expanded = value.expand(variables, values);
+            if value.has_variables() {
+                track!(validate_header(name, &expanded))?;
+            }
+            headers.insert(name.clone(), expanded);
+        }
+        Ok(Request {
+            method: self.method.clone(),
+            url,
+            content: self.content.as_ref().map(|c| match c {
+                CompiledContent::Size(size) => Content::Size(*size),
+                CompiledContent::Text(text) => Content::Text(text.expand(variables, values)),
+            }),
+            timeout: self.timeout,
+            start_time: self.start_time,
+            headers,
+            expect: self.expect.clone(),
+            name: self.name.clone(),
+            tags: self.tags.clone(),
+        })
+    }
+}
+
+#[derive(Debug)]
+enum CompiledContent {
+    Size(usize),
+    Text(Text),
+}
+
+/// A string with placeholders.
+#[derive(Debug)]
+struct Text(Vec<Segment>);
+impl Text {
+    fn parse(mut s: &str, names: &[String], variables: &[VariableState]) -> Result<Self> {
+        let mut segments = Vec::new();
+        while let Some((literal, placeholder, rest)) = track!(split_placeholder(s))? {
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(literal.to_owned()));
+            }
+            let (name, column) = match placeholder.split_once('.') {
+                Some((name, column)) => (name, Some(column)),
+                None => (placeholder, None),
+            };
+            let index = track_assert_some!(
+                names.iter().position(|n| n == name),
+                ErrorKind::Other,
+                "Undefined variable: {:?}",
+                name
+            );
+            let column = match (&variables[index], column) {
+                (VariableState::Csv { table, .. }, Some(column)) => Some(track_assert_some!(
+                    table.header.iter().position(|c| c == column),
+                    ErrorKind::Other,
+                    "Unknown column: {:?}",
+                    placeholder
+                )),
+                (VariableState::Csv { .. }, None) => {
+                    track_panic!(ErrorKind::Other, "A column must be specified: {:?}", name)
+                }
+                (_, None) => None,
+                (_, Some(_)) => {
+                    track_panic!(ErrorKind::Other, "Not a CSV variable: {:?}", placeholder)
+                }
+            };
+            segments.push(Segment::Variable { index, column });
+            s = rest;
+        }
+        if !s.is_empty() {
+            segments.push(Segment::Literal(s.to_owned()));
+        }
+        Ok(Text(segments))
+    }
+
+    fn has_variables(&self) -> bool {
+        self.0.iter().any(|s| matches!(s, Segment::Variable { .. }))
+    }
+
+    fn expand(&self, variables: &[VariableState], values: &[Value]) -> String {
+        let mut s = String::new();
+        for segment in &self.0 {
+            match *segment {
+                Segment::Literal(ref literal) => s.push_str(literal),
+                Segment::Variable { index, column } => match (&values[index], &variables[index]) {
+                    (Value::Text(text), _) => s.push_str(text),
+                    (&Value::Row(row), VariableState::Csv { table, .. }) => {
+                        let column = column.expect("Never fails");
+                        s.push_str(table.rows[row].get(column).map_or("", |v| v.as_str()));
+                    }
+                    _ => unreachable!(),
+                },
+            }
+        }
+        s
+    }
+}
+
+/// Splits `"a{{ b }}c"` into `("a", "b", "c")` (`None` if there are no placeholders).
+pub(crate) fn split_placeholder(s: &str) -> Result<Option<(&str, &str, &str)>> {
+    let start = match s.find("{{") {
+        Some(start) => start,
+        None => return Ok(None),
+    };
+    let end = track_assert_some!(s[start..].find("}}"), ErrorKind::Other, "Unclosed `{{`");
+    let end = start + end;
+    Ok(Some((&s[..start], s[start + 2..end].trim(), &s[end + 2..])))
+}
+
+#[derive(Debug)]
+enum Segment {
+    Literal(String),
+    Variable { index: usize, column: Option<usize> },
+}
+
+#[derive(Debug)]
+struct CsvTable {
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+impl CsvTable {
+    fn parse(text: &str) -> Result<Self> {
+        let mut records = parse_csv(text).into_iter();
+        let header = track_assert_some!(records.next(), ErrorKind::Other, "Empty CSV");
+        let rows = records.collect::<Vec<_>>();
+        track_assert!(!rows.is_empty(), ErrorKind::Other, "No CSV rows");
+        Ok(CsvTable { header, rows })
+    }
+}
+
+/// Parses CSV text (RFC 4180; fields may be quoted with `"`).
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records.retain(|r| !(r.len() == 1 && r[0].is_empty()));
+    records
+}
+
+/// SplitMix64 (a small PRNG whose output only depends on the seed).
+#[derive(Debug)]
+pub(crate) struct Rng(u64);
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_csv_works() {
+        let records = parse_csv("id,name\r\n1,\"a, \"\"b\"\"\"\n2,c\n\n");
+        assert_eq!(
+            records,
+            vec![
+                vec!["id".to_owned(), "name".to_owned()],
+                vec!["1".to_owned(), "a, \"b\"".to_owned()],
+                vec!["2".to_owned(), "c".to_owned()],
+            ]
+        );
+    }
+
+    #[test]
+    fn expansion_is_reproducible() {
+        let file: TemplateFile = serdeconv::from_json_str(
+            r#"{"variables": {"id": {"type": "uuid"}, "n": {"type": "random_int", "min": 1, "max": 9}},
+                "requests": [{"method": "GET", "url": "http://localhost/{{n}}/{{id}}"}]}"#,
+        )
+        .unwrap();
+        let urls = |seed| {
+            RequestGenerator::new(file.clone(), Some(seed), Some(10), false)
+                .unwrap()
+                .map(|r| r.unwrap().url.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(urls(1), urls(1));
+        assert_ne!(urls(1), urls(2));
+        assert_eq!(urls(1).len(), 10);
+    }
+
+    #[test]
+    fn expansion_failures_stop_the_queue() {
+        let file: TemplateFile = serdeconv::from_json_str(
+            r#"{"variables": {"n": {"type": "sequence"}},
+                "requests": [{"method": "GET", "url": "{{n}}"}]}"#,
+        )
+        .unwrap();
+        let mut generator = RequestGenerator::new(file.clone(), None, None, true).unwrap();
+        assert!((0..3).all(|_| generator.next().unwrap().is_err()));
+
+        let generator = RequestGenerator::new(file, None, None, true).unwrap();
+        let queue = crate::run::RequestQueue::with_fallible_generator(generator);
+        assert!(queue.pop().is_err());
+        assert!(queue.pop().is_err());
+    }
+}
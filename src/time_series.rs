@@ -1,48 +1,151 @@
-use crate::run::{RequestResult, Seconds};
-use serde::Serialize;
+use crate::histogram::LatencyHistogram;
+use crate::run::{self, RequestResult};
+use crate::summary::Percentiles;
+use crate::ErrorKind;
+use serde::{Serialize, Serializer};
 use std::collections::BTreeMap;
+use std::time::Duration;
 
-#[derive(Debug, Serialize)]
-pub struct TimeSeries(Vec<Item>);
-impl TimeSeries {
-    pub fn new(results: Vec<RequestResult>) -> Self {
+/// Which time of a request determines its bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BucketBy {
+    StartTime,
+    EndTime,
+}
+
+#[derive(Debug, Clone)]
+pub struct TimeSeriesBuilder {
+    interval: Duration,
+    bucket_by: BucketBy,
+    percentiles: Vec<f64>,
+}
+impl TimeSeriesBuilder {
+    pub fn new() -> Self {
+        TimeSeriesBuilder::default()
+    }
+
+    /// Sets the width of the buckets (which must be positive).
+    ///
+    /// The default value is 1 second.
+    pub fn interval(&mut self, interval: Duration) -> &mut Self {
+        self.interval = interval;
+        self
+    }
+
+    /// The default value is `BucketBy::StartTime`.
+    pub fn bucket_by(&mut self, bucket_by: BucketBy) -> &mut Self {
+        self.bucket_by = bucket_by;
+        self
+    }
+
+    /// Sets the latency percentiles reported for each bucket.
+    ///
+    /// The default value is empty.
+    pub fn percentiles(&mut self, percentiles: Vec<f64>) -> &mut Self {
+        self.percentiles = percentiles;
+        self
+    }
+
+    pub fn finish(&self, results: Vec<RequestResult>) -> TimeSeries {
         let mut items = BTreeMap::new();
         let mut latencies = BTreeMap::new();
+        let mut phases = BTreeMap::new();
         for r in results {
-            let time = r.start_time().0 as usize;
-            let item = items.entry(time).or_insert_with(|| Item {
-                time,
+            let t = match self.bucket_by {
+                BucketBy::StartTime => r.start_time(),
+                BucketBy::EndTime => r.end_time(),
+            };
+            let bucket = (t.0 / self.interval.as_secs_f64()).floor() as u32;
+            let item = items.entry(bucket).or_insert_with(|| Item {
+                time: (self.interval * bucket).as_secs_f64(),
                 ..Item::default()
             });
             item.requests += 1;
+            if let Some(response) = r.response() {
+                *item.status.entry(response.status).or_insert(0) += 1;
+            }
+            if let Some(kind) = r.error_kind() {
+                *item.errors.entry(kind).or_insert(0) += 1;
+            }
 
             latencies
-                .entry(time)
-                .or_insert_with(Vec::new)
-                .push(r.elapsed().0);
+                .entry(bucket)
+                .or_insert_with(LatencyHistogram::new)
+                .record(r.elapsed());
+            if let Some(p) = r.phases() {
+                phases
+                    .entry(bucket)
+                    .or_insert_with(PhasesAccumulator::default)
+                    .add(p);
+            }
         }
         TimeSeries(
             items
                 .into_iter()
-                .map(|(time, mut item)| {
-                    let latencies = latencies.get_mut(&time).unwrap();
-                    latencies.sort_by_key(|l| Seconds(*l));
-                    item.latency.min = latencies[0];
-                    item.latency.median = latencies[latencies.len() / 2];
-                    item.latency.mean = latencies.iter().sum::<f64>() / latencies.len() as f64;
-                    item.latency.max = *latencies.last().unwrap();
+                .map(|(bucket, mut item)| {
+                    let latencies = &latencies[&bucket];
+                    item.latency.min = latencies.min().0;
+                    item.latency.median = latencies.percentile(50.0).0;
+                    item.latency.mean = latencies.mean().0;
+                    item.latency.max = latencies.max().0;
+                    item.latency.percentiles = Percentiles(
+                        self.percentiles
+                            .iter()
+                            .map(|&p| (p, latencies.percentile(p)))
+                            .collect(),
+                    );
+                    item.phases = phases.get(&bucket).map(|p| p.mean());
                     item
                 })
                 .collect(),
         )
     }
 }
+impl Default for TimeSeriesBuilder {
+    fn default() -> Self {
+        TimeSeriesBuilder {
+            interval: Duration::from_secs(1),
+            bucket_by: BucketBy::StartTime,
+            percentiles: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimeSeries(Vec<Item>);
+impl TimeSeries {
+    pub fn new(results: Vec<RequestResult>) -> Self {
+        TimeSeriesBuilder::new().finish(results)
+    }
+}
 
 #[derive(Debug, Default, Serialize)]
 pub struct Item {
-    pub time: usize, // seconds
+    /// Start of the bucket in seconds.
+    ///
+    /// It is serialized as an integer if it is a whole number (e.g., the interval is 1 second).
+    #[serde(serialize_with = "serialize_time")]
+    pub time: f64,
     pub requests: usize,
+    pub status: BTreeMap<u16, usize>,
+
+    /// Number of the errors by kind.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub errors: BTreeMap<ErrorKind, usize>,
     pub latency: Latency,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phases: Option<Phases>,
+}
+
+fn serialize_time<S>(time: &f64, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    if time.fract() == 0.0 && *time >= 0.0 && *time <= u64::MAX as f64 {
+        serializer.serialize_u64(*time as u64)
+    } else {
+        serializer.serialize_f64(*time)
+    }
 }
 
 #[derive(Debug, Default, Serialize)]
@@ -51,4 +154,131 @@ pub struct Latency {
     pub mean: f64,
     pub median: f64,
     pub max: f64,
+    #[serde(skip_serializing_if = "Percentiles::is_empty")]
+    pub percentiles: Percentiles,
+}
+
+/// Mean durations of the request phases.
+#[derive(Debug, Default, Serialize)]
+pub struct Phases {
+    pub dns: f64,
+    pub connect: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<f64>,
+    pub ttfb: f64,
+    pub transfer: f64,
+}
+
+#[derive(Debug, Default)]
+struct PhasesAccumulator {
+    count: usize,
+    tls_count: usize,
+    sum: Phases,
+}
+impl PhasesAccumulator {
+    fn add(&mut self, p: &run::Phases) {
+        self.count += 1;
+        self.sum.dns += p.dns.0;
+        self.sum.connect += p.connect.0;
+        if let Some(tls) = p.tls {
+            self.tls_count += 1;
+            *self.sum.tls.get_or_insert(0.0) += tls.0;
+        }
+        self.sum.ttfb += p.ttfb.0;
+        self.sum.transfer += p.transfer.0;
+    }
+    fn mean(&self) -> Phases {
+        let n = self.count as f64;
+        Phases {
+            dns: self.sum.dns / n,
+            connect: self.sum.connect / n,
+            tls: self.sum.tls.map(|tls| tls / self.tls_count as f64),
+            ttfb: self.sum.ttfb / n,
+            transfer: self.sum.transfer / n,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::run::{Labels, Seconds};
+    use crate::Error;
+
+    fn results() -> Vec<RequestResult> {
+        let error = RequestResult::Error {
+            seq_no: 3,
+            end_time: Seconds(2.2),
+            elapsed: Seconds(0.4),
+            scheduled_start_time: None,
+            labels: Labels::default(),
+            error: Error::from(ErrorKind::Timeout),
+        };
+        vec![
+            RequestResult::ok_for_test(0, 0.3, 0.1),
+            RequestResult::ok_for_test(1, 0.9, 0.3),
+            RequestResult::ok_for_test(2, 1.2, 0.4),
+            error,
+        ]
+    }
+
+    fn buckets(series: &TimeSeries) -> Vec<(f64, usize)> {
+        series.0.iter().map(|i| (i.time, i.requests)).collect()
+    }
+
+    #[test]
+    fn time_series_works() {
+        let series = TimeSeries::new(results());
+        assert_eq!(buckets(&series), [(0.0, 3), (1.0, 1)]);
+        assert_eq!(series.0[0].status.get(&200), Some(&3));
+        assert!(series.0[0].errors.is_empty());
+        assert!(series.0[1].status.is_empty());
+        assert_eq!(series.0[1].errors.get(&ErrorKind::Timeout), Some(&1));
+        assert!((series.0[0].latency.max - 0.4).abs() < 0.001);
+        assert!(series.0[0].latency.percentiles.is_empty());
+    }
+
+    #[test]
+    fn interval_and_bucket_by_work() {
+        let mut builder = TimeSeriesBuilder::new();
+        builder.interval(Duration::from_millis(500));
+        assert_eq!(
+            buckets(&builder.finish(results())),
+            [(0.0, 1), (0.5, 2), (1.5, 1)]
+        );
+
+        builder.bucket_by(BucketBy::EndTime);
+        assert_eq!(
+            buckets(&builder.finish(results())),
+            [(0.0, 1), (0.5, 1), (1.0, 1), (2.0, 1)]
+        );
+    }
+
+    #[test]
+    fn percentiles_work() {
+        let results = (1..=100)
+            .map(|i| RequestResult::ok_for_test(i, 1.5, i as f64 / 1000.0))
+            .collect();
+        let series = TimeSeriesBuilder::new()
+            .percentiles(vec![50.0, 99.0])
+            .finish(results);
+        let percentiles = &series.0[0].latency.percentiles.0;
+        assert_eq!(percentiles.len(), 2);
+        assert!((percentiles[0].1 .0 - 0.05).abs() < 0.001);
+        assert!((percentiles[1].1 .0 - 0.099).abs() < 0.001);
+    }
+
+    #[test]
+    fn time_is_serialized_as_integer_if_possible() {
+        let json = |time| {
+            serde_json::to_value(Item {
+                time,
+                ..Item::default()
+            })
+            .unwrap()["time"]
+                .to_string()
+        };
+        assert_eq!(json(3.0), "3");
+        assert_eq!(json(0.5), "0.5");
+    }
 }
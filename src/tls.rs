@@ -0,0 +1,251 @@
+use crate::{Error, ErrorKind, Result};
+use fibers::net::TcpStream;
+use futures::{Async, Future, Poll};
+use native_tls::{Certificate, HandshakeError, Identity, MidHandshakeTlsStream, TlsStream};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+#[derive(Debug, Default, Clone)]
+pub struct TlsConnectorBuilder {
+    ca_cert_files: Vec<PathBuf>,
+    insecure: bool,
+    client_identity: Option<(PathBuf, PathBuf)>,
+}
+impl TlsConnectorBuilder {
+    pub fn new() -> Self {
+        TlsConnectorBuilder::default()
+    }
+
+    /// Adds a PEM encoded CA certificate (bundle) to trust in addition to the system ones.
+    pub fn ca_cert_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.ca_cert_files.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// If `true`, server certificates and hostnames are not verified.
+    pub fn insecure(&mut self, insecure: bool) -> &mut Self {
+        self.insecure = insecure;
+        self
+    }
+
+    /// Sets the PEM encoded certificate (chain) and PKCS#8 private key used for client authentication (mTLS).
+    pub fn client_identity<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        cert_file: P,
+        key_file: Q,
+    ) -> &mut Self {
+        self.client_identity = Some((
+            cert_file.as_ref().to_path_buf(),
+            key_file.as_ref().to_path_buf(),
+        ));
+        self
+    }
+
+    pub fn finish(&self) -> Result<TlsConnector> {
+        let mut builder = native_tls::TlsConnector::builder();
+        for path in &self.ca_cert_files {
+            let pem = track!(fs::read(path).map_err(Error::from); path)?;
+            for cert in track!(split_pem_certificates(&pem); path)? {
+                let cert = track!(Certificate::from_pem(cert).map_err(Error::from); path)?;
+                builder.add_root_certificate(cert);
+            }
+        }
+        if let Some((cert_file, key_file)) = &self.client_identity {
+            let cert = track!(fs::read(cert_file).map_err(Error::from); cert_file)?;
+            let key = track!(fs::read(key_file).map_err(Error::from); key_file)?;
+            let identity = track!(Identity::from_pkcs8(&cert, &key).map_err(Error::from))?;
+            builder.identity(identity);
+        }
+        builder
+            .danger_accept_invalid_certs(self.insecure)
+            .danger_accept_invalid_hostnames(self.insecure);
+        let inner = track!(builder.build().map_err(Error::from))?;
+        Ok(TlsConnector { inner })
+    }
+}
+
+#[derive(Clone)]
+pub struct TlsConnector {
+    inner: native_tls::TlsConnector,
+}
+impl TlsConnector {
+    pub fn new() -> Result<Self> {
+        TlsConnectorBuilder::new().finish()
+    }
+
+    /// Returns a connector with the default settings, which is made (only once) on the first call.
+    pub fn shared_default() -> Result<Self> {
+        static DEFAULT: OnceLock<Result<TlsConnector>> = OnceLock::new();
+        DEFAULT.get_or_init(TlsConnector::new).clone()
+    }
+
+    pub(crate) fn connect(&self, domain: &str, stream: TcpStream) -> Handshake {
+        Handshake(Some(HandshakeState::Start {
+            connector: self.inner.clone(),
+            domain: domain.to_owned(),
+            stream,
+        }))
+    }
+}
+impl std::fmt::Debug for TlsConnector {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "TlsConnector {{ .. }}")
+    }
+}
+
+pub(crate) struct Handshake(Option<HandshakeState>);
+impl Future for Handshake {
+    type Item = TlsStream<TcpStream>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = match self.0.take().expect("Never fails") {
+            HandshakeState::Start {
+                connector,
+                domain,
+                stream,
+            } => connector.connect(&domain, stream),
+            HandshakeState::Mid(stream) => stream.handshake(),
+        };
+        match result {
+            Ok(stream) => Ok(Async::Ready(stream)),
+            Err(HandshakeError::WouldBlock(stream)) => {
+                self.0 = Some(HandshakeState::Mid(stream));
+                Ok(Async::NotReady)
+            }
+            Err(HandshakeError::Failure(e)) => Err(track!(Error::from(e))),
+        }
+    }
+}
+
+enum HandshakeState {
+    Start {
+        connector: native_tls::TlsConnector,
+        domain: String,
+        stream: TcpStream,
+    },
+    Mid(MidHandshakeTlsStream<TcpStream>),
+}
+
+/// Splits a PEM bundle into individual certificates, since `native_tls` can only load one at a time.
+fn split_pem_certificates(pem: &[u8]) -> Result<Vec<&[u8]>> {
+    const END: &[u8] = b"-----END CERTIFICATE-----";
+    let mut certs = Vec::new();
+    let mut rest = pem;
+    while let Some(i) = rest.windows(END.len()).position(|w| w == END) {
+        let (cert, tail) = rest.split_at(i + END.len());
+        certs.push(cert);
+        rest = tail;
+    }
+    track_assert!(
+        !certs.is_empty(),
+        ErrorKind::InvalidInput,
+        "No PEM certificates"
+    );
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fibers::{Executor, InPlaceExecutor, Spawn};
+    use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/localhost.crt");
+    const KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/localhost.key");
+
+    /// Accepts a connection on a new thread, which returns whether `accept` succeeded.
+    fn serve<F>(accept: F) -> (u16, thread::JoinHandle<bool>)
+    where
+        F: FnOnce(std::net::TcpStream) -> bool + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || accept(listener.accept().unwrap().0));
+        (port, server)
+    }
+
+    fn handshake(tls: &TlsConnector, port: u16) -> Result<()> {
+        let addr = format!("127.0.0.1:{}", port).parse().unwrap();
+        let tls = tls.clone();
+        let future = TcpStream::connect(addr)
+            .map_err(Error::from)
+            .and_then(move |stream| tls.connect("localhost", stream))
+            .map(|_| ());
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.handle().spawn_monitor(future);
+        executor.run_fiber(monitor).unwrap().map_err(Error::from)
+    }
+
+    fn native_tls_acceptor() -> native_tls::TlsAcceptor {
+        let identity =
+            Identity::from_pkcs8(&fs::read(CERT).unwrap(), &fs::read(KEY).unwrap()).unwrap();
+        native_tls::TlsAcceptor::new(identity).unwrap()
+    }
+
+    #[test]
+    fn insecure_works() {
+        let acceptor = native_tls_acceptor();
+        let (port, server) = serve(move |stream| acceptor.accept(stream).is_ok());
+        let tls = TlsConnector::new().unwrap();
+        assert!(handshake(&tls, port).is_err());
+        server.join().unwrap();
+
+        let acceptor = native_tls_acceptor();
+        let (port, server) = serve(move |stream| acceptor.accept(stream).is_ok());
+        let tls = TlsConnectorBuilder::new().insecure(true).finish().unwrap();
+        assert!(handshake(&tls, port).is_ok());
+        assert!(server.join().unwrap());
+    }
+
+    #[test]
+    fn client_identity_works() {
+        let serve_mtls = || {
+            let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+            acceptor
+                .set_private_key_file(KEY, SslFiletype::PEM)
+                .unwrap();
+            acceptor.set_certificate_chain_file(CERT).unwrap();
+            acceptor.set_ca_file(CERT).unwrap();
+            acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+            let acceptor = acceptor.build();
+            serve(move |stream| acceptor.accept(stream).is_ok())
+        };
+
+        let (port, server) = serve_mtls();
+        let tls = TlsConnectorBuilder::new()
+            .ca_cert_file(CERT)
+            .client_identity(CERT, KEY)
+            .finish()
+            .unwrap();
+        assert!(handshake(&tls, port).is_ok());
+        assert!(server.join().unwrap());
+
+        // With TLS 1.3, the client may finish its handshake before the server rejects it.
+        let (port, server) = serve_mtls();
+        let tls = TlsConnectorBuilder::new()
+            .ca_cert_file(CERT)
+            .finish()
+            .unwrap();
+        let _ = handshake(&tls, port);
+        assert!(!server.join().unwrap());
+    }
+
+    #[test]
+    fn file_without_certificates_is_rejected() {
+        let e = TlsConnectorBuilder::new()
+            .ca_cert_file(KEY)
+            .finish()
+            .unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        assert!(split_pem_certificates(b"").is_err());
+        assert_eq!(
+            split_pem_certificates(&fs::read(CERT).unwrap())
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}